@@ -0,0 +1,478 @@
+use std::collections::BTreeMap;
+
+use bevy::{asset::ron, ecs::system::SystemParam, input::{gamepad::{GamepadConnection, GamepadConnectionEvent}, InputSystem}, prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::weapon::WEAPON_SLOTS;
+
+// next to the executable's working directory, the web build keeps the defaults
+const SETTINGS_PATH: &str = "input.settings.ron";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Dash,
+    // quick turn of the camera
+    Spin,
+    ToggleCamera,
+    Attack,
+    // picks the weapon in that slot, counted from 0
+    Weapon(u8),
+    // asks for a new binding of every action in turn
+    Rebind,
+}
+
+impl Action {
+
+    // in the order the rebinding goes through them
+    fn rebindable() -> Vec<Action> {
+        let mut actions = vec![
+            Action::MoveForward, Action::MoveBackward, Action::MoveLeft, Action::MoveRight,
+            Action::Jump, Action::Dash, Action::Spin, Action::ToggleCamera, Action::Attack,
+        ];
+        actions.extend((0..WEAPON_SLOTS.len() as u8).map(Action::Weapon));
+        actions
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    // on whichever gamepad is active
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+
+    fn on_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StickSettings {
+    // radial, the tilt is rescaled so full tilt still reads 1
+    pub dead_zone: f32,
+    // the tilt is raised to this power, above 1 gives finer control near the centre
+    pub exponent: f32,
+}
+
+impl Default for StickSettings {
+    fn default() -> Self {
+        StickSettings { dead_zone: 0.15, exponent: 1. }
+    }
+}
+
+impl StickSettings {
+
+    pub fn shape(&self, raw: Vec2) -> Vec2 {
+        let tilt = raw.length();
+        if tilt <= self.dead_zone {
+            return Vec2::ZERO;
+        }
+        let scaled = ((tilt - self.dead_zone) / (1. - self.dead_zone).max(f32::EPSILON)).min(1.);
+        raw / tilt * scaled.powf(self.exponent)
+    }
+}
+
+// Every binding of an action triggers it, saved as RON in SETTINGS_PATH.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputMap {
+    pub bindings: BTreeMap<Action, Vec<Binding>>,
+    pub move_stick: StickSettings,
+    pub look_stick: StickSettings,
+    // mouse pixels per second the right stick stands for at full tilt
+    pub look_sensitivity: (f32, f32),
+    pub invert_look_y: bool,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut bindings = BTreeMap::from([
+            (Action::MoveForward, vec![Binding::Key(KeyCode::KeyW)]),
+            (Action::MoveBackward, vec![Binding::Key(KeyCode::KeyS)]),
+            (Action::MoveLeft, vec![Binding::Key(KeyCode::KeyA)]),
+            (Action::MoveRight, vec![Binding::Key(KeyCode::KeyD)]),
+            (Action::Jump, vec![Binding::Key(KeyCode::Space), Binding::Gamepad(GamepadButtonType::South)]),
+            (Action::Dash, vec![Binding::Key(KeyCode::KeyR), Binding::Gamepad(GamepadButtonType::East)]),
+            (Action::Spin, vec![Binding::Key(KeyCode::KeyT), Binding::Gamepad(GamepadButtonType::RightThumb)]),
+            (Action::ToggleCamera, vec![Binding::Key(KeyCode::Tab), Binding::Gamepad(GamepadButtonType::Select)]),
+            (Action::Attack, vec![Binding::Mouse(MouseButton::Left), Binding::Gamepad(GamepadButtonType::RightTrigger2)]),
+            (Action::Rebind, vec![Binding::Key(KeyCode::F1)]),
+        ]);
+        let digits = [
+            KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3,
+            KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6,
+            KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+        ];
+        for (slot, key) in digits.into_iter().enumerate() {
+            bindings.insert(Action::Weapon(slot as u8), vec![Binding::Key(key)]);
+        }
+        InputMap {
+            bindings,
+            move_stick: StickSettings::default(),
+            look_stick: StickSettings { dead_zone: 0.1, exponent: 2. },
+            look_sensitivity: (1200., 800.),
+            invert_look_y: false,
+        }
+    }
+}
+
+impl InputMap {
+
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        for (other, bindings) in self.bindings.iter() {
+            if *other != action && bindings.contains(&binding) {
+                warn!("{:?} is also bound to {:?}", binding, other);
+            }
+        }
+        // the keyboard and mouse binding and the gamepad one are replaced separately
+        let bindings = self.bindings.entry(action).or_default();
+        bindings.retain(|other| other.on_gamepad() != binding.on_gamepad());
+        bindings.push(binding);
+    }
+
+    // actions missing from the file keep their default bindings
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        match std::fs::read_to_string(SETTINGS_PATH) {
+            Ok(text) => match ron::de::from_str::<InputMap>(&text) {
+                Ok(mut saved) => {
+                    for (action, bindings) in InputMap::default().bindings {
+                        saved.bindings.entry(action).or_insert(bindings);
+                    }
+                    return saved;
+                }
+                Err(error) => warn!("invalid input settings {}: {}", SETTINGS_PATH, error),
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => (),
+            Err(error) => warn!("could not read input settings {}: {}", SETTINGS_PATH, error),
+        }
+        InputMap::default()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        InputMap::default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) {
+        let text = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(text) => text,
+            Err(error) => {
+                warn!("could not serialize input settings: {}", error);
+                return;
+            }
+        };
+        match std::fs::write(SETTINGS_PATH, text) {
+            Ok(()) => info!("input settings saved to {}", SETTINGS_PATH),
+            Err(error) => warn!("could not write input settings {}: {}", SETTINGS_PATH, error),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) {
+        warn!("input settings are not saved on the web");
+    }
+}
+
+// What the bindings amount to this frame, read by gameplay instead of the devices.
+#[derive(Resource, Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    // x to the right, y forward, keys give full length and the stick its tilt
+    pub movement: Vec2,
+    // in mouse pixels per second, oriented like MouseMotion
+    pub look: Vec2,
+}
+
+impl Actions {
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+// Gamepad the bindings and sticks are read from, the first one plugged in.
+#[derive(Resource, Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
+
+// Every device the bindings are read from.
+#[derive(SystemParam)]
+struct Devices<'w> {
+    keyboard: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    gamepad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    active: Res<'w, ActiveGamepad>,
+}
+
+impl Devices<'_> {
+
+    // pressed and just pressed
+    fn read(&self, binding: Binding) -> (bool, bool) {
+        match binding {
+            Binding::Key(key) => (self.keyboard.pressed(key), self.keyboard.just_pressed(key)),
+            Binding::Mouse(button) => (self.mouse.pressed(button), self.mouse.just_pressed(button)),
+            Binding::Gamepad(button) => match self.active.0 {
+                Some(gamepad) => {
+                    let button = GamepadButton::new(gamepad, button);
+                    (self.gamepad_buttons.pressed(button), self.gamepad_buttons.just_pressed(button))
+                }
+                None => (false, false),
+            },
+        }
+    }
+
+    fn stick(&self, x: GamepadAxisType, y: GamepadAxisType) -> Option<Vec2> {
+        let gamepad = self.active.0?;
+        Some(Vec2::new(
+            self.axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.),
+            self.axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.),
+        ))
+    }
+}
+
+// Actions still waiting for a binding, the next one last, and the bindings
+// from before the session so cancelling it can put them back.
+#[derive(Resource, Default)]
+struct Rebinding {
+    pending: Vec<Action>,
+    previous: Option<InputMap>,
+}
+
+impl Rebinding {
+
+    fn active(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+#[derive(Component)]
+struct RebindPrompt;
+
+pub struct ActionsPlugin;
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App){
+
+        app
+        .insert_resource(InputMap::load())
+        .init_resource::<Actions>()
+        .init_resource::<Rebinding>()
+        .init_resource::<ActiveGamepad>()
+        .add_systems(Startup, spawn_rebind_prompt)
+        .add_systems(PreUpdate, (track_gamepads, update_actions).chain().after(InputSystem))
+        .add_systems(Update, (rebind, update_rebind_prompt).chain());
+    }
+}
+
+fn track_gamepads(
+    mut connections: EventReader<GamepadConnectionEvent>,
+    gamepads: Res<Gamepads>,
+    mut active: ResMut<ActiveGamepad>,
+){
+    for event in connections.read() {
+        match &event.connection {
+            GamepadConnection::Connected(info) => {
+                info!("gamepad {} connected: {}", event.gamepad.id, info.name);
+                if active.0.is_none() {
+                    active.0 = Some(event.gamepad);
+                }
+            }
+            GamepadConnection::Disconnected => {
+                info!("gamepad {} disconnected", event.gamepad.id);
+                if active.0 == Some(event.gamepad) {
+                    // hand over to another pad still plugged in
+                    active.0 = gamepads.iter().find(|gamepad| *gamepad != event.gamepad);
+                }
+            }
+        }
+    }
+}
+
+fn update_actions(
+    devices: Devices,
+    map: Res<InputMap>,
+    rebinding: Res<Rebinding>,
+    mut actions: ResMut<Actions>,
+){
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    actions.movement = Vec2::ZERO;
+    actions.look = Vec2::ZERO;
+
+    // the keys pressed while rebinding do not play
+    if rebinding.active() {
+        return;
+    }
+
+    for (action, bindings) in map.bindings.iter() {
+        for binding in bindings {
+            let (pressed, just_pressed) = devices.read(*binding);
+            if pressed {
+                actions.pressed.insert(*action);
+            }
+            if just_pressed {
+                actions.just_pressed.insert(*action);
+            }
+        }
+    }
+
+    let mut movement = Vec2::ZERO;
+    for (action, direction) in [
+        (Action::MoveForward, Vec2::Y), (Action::MoveBackward, Vec2::NEG_Y),
+        (Action::MoveRight, Vec2::X), (Action::MoveLeft, Vec2::NEG_X),
+    ] {
+        if actions.pressed(action) {
+            movement += direction;
+        }
+    }
+    movement = movement.normalize_or_zero();
+
+    if let Some(stick) = devices.stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) {
+        movement += map.move_stick.shape(stick);
+    }
+    if let Some(stick) = devices.stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY) {
+        // pushing the stick up looks up, the mouse does it moving down the screen
+        let look = map.look_stick.shape(stick);
+        let (x, y) = map.look_sensitivity;
+        let y = if map.invert_look_y { y } else { -y };
+        actions.look = Vec2::new(look.x * x, look.y * y);
+    }
+    actions.movement = movement.clamp_length_max(1.);
+}
+
+fn spawn_rebind_prompt(
+    mut commands: Commands,
+){
+    commands.spawn((RebindPrompt, TextBundle::from_section("", TextStyle{color: Color::WHITE, font_size: 28.0, ..default()})
+        .with_text_justify(JustifyText::Center)
+        .with_style(Style{
+            position_type: PositionType::Absolute,
+            top: Val::Percent(20.),
+            width: Val::Percent(100.),
+            ..default()
+        })
+        .with_background_color(Color::rgba(0., 0., 0., 0.6)),
+    )).insert(Visibility::Hidden);
+}
+
+fn update_rebind_prompt(
+    map: Res<InputMap>,
+    rebinding: Res<Rebinding>,
+    mut prompts: Query<(&mut Text, &mut Visibility), With<RebindPrompt>>,
+){
+    if !rebinding.is_changed() {
+        return;
+    }
+    for (mut text, mut visibility) in prompts.iter_mut() {
+        let Some(&action) = rebinding.pending.last() else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        let current = map.bindings.get(&action).cloned().unwrap_or_default();
+        let cancel = map.bindings.get(&Action::Rebind).cloned().unwrap_or_default();
+        text.sections[0].value = format!(
+            "Press a key or a button for {:?}\ncurrently {:?}\n\nEscape keeps it, {:?} cancels every change",
+            action, current, cancel,
+        );
+        *visibility = Visibility::Inherited;
+    }
+}
+
+// the next press replaces the bindings of the prompted action, the file is
+// saved once every action went through. Held keys are only read once, so the
+// OS key repeat does not bind one key to several actions.
+fn rebind(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    actions: Res<Actions>,
+    mut map: ResMut<InputMap>,
+    mut rebinding: ResMut<Rebinding>,
+){
+    let Some(&action) = rebinding.pending.last() else {
+        if actions.just_pressed(Action::Rebind) {
+            let mut pending = Action::rebindable();
+            pending.reverse();
+            *rebinding = Rebinding { pending, previous: Some(map.clone()) };
+        }
+        return;
+    };
+
+    let Some(binding) = keyboard.get_just_pressed().map(|key| Binding::Key(*key))
+        .chain(mouse.get_just_pressed().map(|button| Binding::Mouse(*button)))
+        .chain(gamepad_buttons.get_just_pressed().map(|button| Binding::Gamepad(button.button_type)))
+        .next() else {
+        return;
+    };
+
+    let cancels = map.bindings.get(&Action::Rebind).is_some_and(|bindings| bindings.contains(&binding));
+    if cancels {
+        if let Some(previous) = rebinding.previous.take() {
+            *map = previous;
+        }
+        rebinding.pending.clear();
+        info!("rebinding cancelled");
+        return;
+    }
+
+    if binding != Binding::Key(KeyCode::Escape) {
+        map.bind(action, binding);
+    }
+    rebinding.pending.pop();
+    if !rebinding.active() {
+        rebinding.previous = None;
+        map.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::StickSettings;
+
+    #[test]
+    fn dead_zone_reads_zero() {
+        let stick = StickSettings { dead_zone: 0.2, exponent: 1. };
+        assert_eq!(stick.shape(Vec2::new(0.1, 0.1)), Vec2::ZERO);
+        assert_eq!(stick.shape(Vec2::new(0., -0.2)), Vec2::ZERO);
+        assert!(stick.shape(Vec2::new(0., 0.25)).y > 0.);
+    }
+
+    #[test]
+    fn full_tilt_still_reads_one() {
+        let stick = StickSettings { dead_zone: 0.2, exponent: 2. };
+        for raw in [Vec2::X, Vec2::NEG_Y, Vec2::new(1., 1.), Vec2::new(-0.8, 0.6)] {
+            let shaped = stick.shape(raw);
+            assert!((shaped.length() - 1.).abs() < 1e-5, "{} gives {}", raw, shaped);
+            assert!(shaped.normalize().abs_diff_eq(raw.normalize(), 1e-5), "{} turned into {}", raw, shaped);
+        }
+    }
+
+    #[test]
+    fn tilt_is_rescaled_past_the_dead_zone() {
+        let stick = StickSettings { dead_zone: 0.2, exponent: 1. };
+        assert!((stick.shape(Vec2::new(0.6, 0.)).x - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn exponent_softens_small_tilts() {
+        let stick = StickSettings { dead_zone: 0., exponent: 2. };
+        assert!((stick.shape(Vec2::new(0., 0.5)).y - 0.25).abs() < 1e-5);
+    }
+}
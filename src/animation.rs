@@ -0,0 +1,220 @@
+use bevy::{prelude::*, utils::{Duration, HashMap}};
+use bevy_rapier3d::dynamics::Velocity;
+
+use crate::asset_loader::GameAssets;
+
+// Every animation player found under a root entity, a glTF scene can hold
+// several (one per armature).
+#[derive(Component)]
+pub struct AnimationEntityLink(pub Vec<Entity>);
+
+// Scenes nested under another entity (view models, the player's body) link
+// their animation players to themselves instead of the top parent.
+#[derive(Component)]
+pub struct AnimationRoot;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AnimState {
+    Idle,
+    Walk,
+    Run,
+    Attack,
+    Hit,
+    Die,
+    Fall,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub path: String,
+    pub speed: f32,
+    // seconds blended from the previous clip
+    pub crossfade: f32,
+    // horizontal velocity at which the clip plays at `speed`, the playback
+    // follows the velocity from there
+    pub reference_velocity: Option<f32>,
+}
+
+impl Clip {
+
+    pub fn new(path: &str) -> Self {
+        Clip { path: path.to_string(), speed: 1., crossfade: 0.2, reference_velocity: None }
+    }
+
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn crossfade(mut self, seconds: f32) -> Self {
+        self.crossfade = seconds;
+        self
+    }
+
+    pub fn scaled_by_velocity(mut self, reference_velocity: f32) -> Self {
+        self.reference_velocity = Some(reference_velocity);
+        self
+    }
+}
+
+// Maps logical states to clips on the root entity. Loop states stay until
+// changed, one shots play once over them and hand back to the loop.
+// States without a clip freeze the current pose.
+#[derive(Component, Default)]
+pub struct AnimationController {
+    clips: HashMap<AnimState, Clip>,
+    state: Option<AnimState>,
+    one_shot: Option<(AnimState, f32)>,
+    restart: bool,
+    playing: Option<AnimState>,
+    // horizontal velocity set by hand, for roots without a Velocity of their own
+    velocity: Option<f32>,
+}
+
+impl AnimationController {
+
+    pub fn with_clip(mut self, state: AnimState, clip: Clip) -> Self {
+        self.clips.insert(state, clip);
+        self
+    }
+
+    pub fn with_state(mut self, state: AnimState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn set_state(&mut self, state: AnimState) {
+        self.state = Some(state);
+    }
+
+    pub fn set_velocity(&mut self, horizontal: f32) {
+        self.velocity = Some(horizontal);
+    }
+
+    // remaining time is filled in once the clip duration is known
+    pub fn trigger(&mut self, state: AnimState) {
+        self.one_shot = Some((state, f32::INFINITY));
+        self.restart = true;
+    }
+}
+
+pub struct AnimationPlugin;
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_systems(Update, (link_animations, drive_animations).chain());
+    }
+}
+
+fn get_top_parent(
+    mut curr_entity: Entity,
+    parent_query: &Query<&Parent>,
+    roots: &Query<(), With<AnimationRoot>>,
+) -> Entity {
+    //Loop up all the way to the top parent
+    loop {
+        if roots.contains(curr_entity) {
+            break;
+        }
+        if let Ok(parent) = parent_query.get(curr_entity) {
+            curr_entity = parent.get();
+        } else {
+            break;
+        }
+    }
+    curr_entity
+}
+
+fn link_animations(
+    player_query: Query<Entity, Added<AnimationPlayer>>,
+    parent_query: Query<&Parent>,
+    roots: Query<(), With<AnimationRoot>>,
+    mut links: Query<&mut AnimationEntityLink>,
+    mut commands: Commands,
+) {
+    // Get all the Animation players which can be deep and hidden in the heirachy
+    let mut new_links: HashMap<Entity, Vec<Entity>> = HashMap::default();
+    for entity in player_query.iter() {
+        let top_entity = get_top_parent(entity, &parent_query, &roots);
+        new_links.entry(top_entity).or_default().push(entity);
+    }
+
+    for (top_entity, players) in new_links {
+        if let Ok(mut link) = links.get_mut(top_entity) {
+            link.0.extend(players);
+        } else {
+            commands.entity(top_entity).insert(AnimationEntityLink(players));
+        }
+    }
+}
+
+fn drive_animations(
+    time: Res<Time>,
+    mut roots: Query<(&mut AnimationController, &AnimationEntityLink, Option<&Velocity>)>,
+    mut players: Query<&mut AnimationPlayer>,
+    clips: Res<Assets<AnimationClip>>,
+    assets: Res<GameAssets>,
+){
+    let dt = time.delta_seconds();
+
+    for (mut controller, link, velocity) in roots.iter_mut() {
+        let controller = &mut *controller;
+
+        if let Some((_, remaining)) = controller.one_shot.as_mut() {
+            *remaining -= dt;
+            if *remaining <= 0. {
+                controller.one_shot = None;
+            }
+        }
+
+        let desired = controller.one_shot.map(|(state, _)| state).or(controller.state);
+        let Some(desired) = desired else {
+            continue;
+        };
+        let clip = controller.clips.get(&desired);
+        let handle = clip.and_then(|clip| assets.get::<AnimationClip>(&clip.path));
+
+        let horizontal = controller.velocity
+            .or(velocity.map(|velocity| Vec2::new(velocity.linvel.x, velocity.linvel.z).length()));
+        let speed = clip.map(|clip| match (clip.reference_velocity, horizontal) {
+            (Some(reference), Some(horizontal)) => clip.speed * (horizontal / reference).clamp(0.25, 3.),
+            _ => clip.speed,
+        }).unwrap_or(1.);
+
+        let changed = controller.playing != Some(desired) || controller.restart;
+        let one_shot = controller.one_shot.is_some_and(|(state, _)| state == desired);
+
+        if changed && one_shot {
+            // now that the clip is known, the one shot lasts as long as it plays
+            let duration = handle.as_ref().and_then(|handle| clips.get(handle)).map(|clip| clip.duration()).unwrap_or(0.);
+            if let Some((_, remaining)) = controller.one_shot.as_mut() {
+                *remaining = duration / speed.max(0.01);
+            }
+        }
+
+        for player in link.0.iter() {
+            let Ok(mut player) = players.get_mut(*player) else {
+                continue;
+            };
+            match (&handle, clip) {
+                (Some(handle), Some(clip)) => {
+                    if changed {
+                        player.resume();
+                        let crossfade = Duration::from_secs_f32(clip.crossfade);
+                        if one_shot {
+                            player.start_with_transition(handle.clone(), crossfade);
+                        } else {
+                            player.play_with_transition(handle.clone(), crossfade).repeat();
+                        }
+                    }
+                    player.set_speed(speed);
+                }
+                _ => if changed { player.pause(); },
+            }
+        }
+
+        controller.playing = Some(desired);
+        controller.restart = false;
+    }
+}
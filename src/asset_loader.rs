@@ -1,18 +1,27 @@
 
-use bevy::{asset:: UntypedAssetId, prelude::*, utils::HashMap};
+use std::any::TypeId;
+
+use bevy::{app::AppExit, asset::{LoadState, RecursiveDependencyLoadState, UntypedAssetId}, prelude::*, utils::{HashMap, HashSet}};
+
+use crate::{manifest::{AssetManifest, MANIFEST_PATH}, ron_loader::RonAssetLoader};
 
 
 #[derive(Default, Resource)]
 pub struct GameAssets{
 
-    requested: HashMap<usize, Vec<UntypedAssetId>>,
+    // still loading, per world
+    requested: HashMap<usize, Vec<(UntypedAssetId, String)>>,
+    loaded: HashMap<usize, usize>,
+    failed: HashMap<usize, Vec<String>>,
+    loading_worlds: Vec<usize>,
+    // world handle holding each preloaded manifest bundle
+    bundles: HashMap<String, usize>,
 
     handles: usize,
-    image: Vec<(Handle<Image>, String)>,
-    meshes: Vec<Handle<Mesh>>,
-    materials: Vec<Handle<StandardMaterial>>,
-    scenes: Vec<(Handle<Scene>, String)>,
-    animations: Vec<(Handle<AnimationClip>, String)>,
+    // path -> handle, per asset type
+    registry: HashMap<TypeId, HashMap<String, UntypedHandle>>,
+    // what each world asked for, its handles are dropped with the last world using them
+    owned: HashMap<usize, HashSet<(TypeId, String)>>,
 }
 
 impl GameAssets {
@@ -24,109 +33,316 @@ impl GameAssets {
         handle
     }
 
-    pub fn request_image(&mut self, world_handle: usize, path: String, server: &Res<AssetServer>) 
-        -> Handle<Image> 
-    {   
+    pub fn request<A: Asset>(&mut self, world_handle: usize, path: &str, server: &AssetServer) -> Handle<A> {
+        let type_id = TypeId::of::<A>();
 
-        if !self.requested.contains_key(&world_handle){
-            self.requested.insert(world_handle, Vec::new());
-        }
+        let handle = match self.get::<A>(path) {
+            Some(handle) => handle,
+            None => {
+                let handle: Handle<A> = server.load(path.to_string());
+                self.registry.entry(type_id).or_default().insert(path.to_string(), handle.clone().untyped());
+                handle
+            },
+        };
 
-        for img in self.image.iter(){
-            if img.1 == path {
-                return img.0.clone();
-            }
+        if self.owned.entry(world_handle).or_default().insert((type_id, path.to_string())) {
+            self.requested.entry(world_handle).or_default().push((handle.id().untyped(), path.to_string()));
         }
-        let handle: Handle<Image> = server.load(path.clone());
-        self.image.push((handle.clone(), path));
-        self.requested.get_mut(&world_handle).unwrap().push(handle.clone().into());
         handle
-        
     }
 
-    pub fn request_scene(&mut self, world_handle: usize, path: String, server: &Res<AssetServer>) 
-    -> Handle<Scene> 
-    {   
+    pub fn get<A: Asset>(&self, path: &str) -> Option<Handle<A>> {
+        self.registry.get(&TypeId::of::<A>())?
+            .get(path)
+            .map(|handle| handle.clone().typed::<A>())
+    }
 
-        if !self.requested.contains_key(&world_handle){
-            self.requested.insert(world_handle, Vec::new());
-        }
+    // forgets the world, assets no other world asked for are unloaded once
+    // the last entity holding their handle is gone
+    pub fn release_world(&mut self, world_handle: usize) {
+        self.requested.remove(&world_handle);
+        self.loaded.remove(&world_handle);
+        self.failed.remove(&world_handle);
+        self.loading_worlds.retain(|loading| *loading != world_handle);
 
-        for scene in self.scenes.iter(){
-            if scene.1 == path {
-                return scene.0.clone();
+        let Some(owned) = self.owned.remove(&world_handle) else {
+            return;
+        };
+        for key in owned {
+            if self.owned.values().any(|other| other.contains(&key)) {
+                continue;
+            }
+            if let Some(handles) = self.registry.get_mut(&key.0) {
+                handles.remove(&key.1);
             }
         }
-        let handle: Handle<Scene> = server.load(path.clone());
-        self.scenes.push((handle.clone(), path));
-        self.requested.get_mut(&world_handle).unwrap().push(handle.clone().into());
-        handle
-    
     }
 
-    pub fn request_animation(&mut self, world_handle: usize, path: String, server: &Res<AssetServer>) 
-    -> Handle<AnimationClip> 
-    {   
+    // the loading state waits on these worlds before moving on
+    pub fn load_world(&mut self, world_handle: usize) {
+        self.requested.entry(world_handle).or_default();
+        if !self.loading_worlds.contains(&world_handle) {
+            self.loading_worlds.push(world_handle);
+        }
+    }
+
+    pub fn loading_worlds(&self) -> &[usize] {
+        &self.loading_worlds
+    }
 
-        if !self.requested.contains_key(&world_handle){
-            self.requested.insert(world_handle, Vec::new());
+    // requests every entry of a manifest bundle under a world of its own,
+    // released as a whole with release_bundle
+    pub fn load_bundle(&mut self, manifest: &AssetManifest, bundle: &str, server: &AssetServer) {
+        if self.bundles.contains_key(bundle) {
+            return;
         }
+        let Some(entries) = manifest.bundles.get(bundle) else {
+            error!("asset manifest has no bundle named {}", bundle);
+            return;
+        };
 
-        for animation in self.animations.iter(){
-            if animation.1 == path {
-                return animation.0.clone();
-            }
+        let world_handle = self.new_world();
+        for entry in entries {
+            entry.request(self, world_handle, server);
         }
-        let handle: Handle<AnimationClip> = server.load(path.clone());
-        self.animations.push((handle.clone(), path));
-        self.requested.get_mut(&world_handle).unwrap().push(handle.clone().into());
-        handle
-    
+        self.bundles.insert(bundle.to_string(), world_handle);
+        self.load_world(world_handle);
     }
 
-    pub fn wait_for_world_assets(&mut self, world_handle: usize, server: &Res<AssetServer>){
-        loop{
+    pub fn release_bundle(&mut self, bundle: &str) {
+        if let Some(world_handle) = self.bundles.remove(bundle) {
+            self.release_world(world_handle);
+        }
+    }
 
-            let mut to_remove: Vec<UntypedAssetId> = Vec::new();
-            for id in self.requested.get(&world_handle).unwrap().iter() {
-    
-                let state = server.get_load_state(*id);
-                match state {
-                    Some(_) => {
-                        to_remove.push(*id);
-                    },
-                    None => (),
-                }
-            }
-            
-            for loaded_assets in to_remove {
-                let mut idx = 0;
-                for id in self.requested.get(&world_handle).unwrap(){
-                    if *id == loaded_assets {
-                        break;
-                    }
-                    idx += 1;
-                }
-                self.requested.get_mut(&world_handle).unwrap().swap_remove(idx);
-            }
-    
-            if self.requested.get(&world_handle).unwrap().is_empty() {
-                break;
+    // sorts the world's pending requests into loaded and failed, scenes count
+    // as loaded once their whole dependency tree is
+    pub fn update_world(&mut self, world_handle: usize, server: &AssetServer) {
+        let Some(pending) = self.requested.get_mut(&world_handle) else {
+            return;
+        };
+
+        let mut loaded = 0;
+        let mut failed = Vec::new();
+        pending.retain(|(id, path)| {
+            let state = server.get_load_state(*id);
+            let dependencies = server.get_recursive_dependency_load_state(*id);
+            match (state, dependencies) {
+                (Some(LoadState::Failed), _) | (_, Some(RecursiveDependencyLoadState::Failed)) => {
+                    failed.push(path.clone());
+                    false
+                },
+                (Some(LoadState::Loaded), Some(RecursiveDependencyLoadState::Loaded)) => {
+                    loaded += 1;
+                    false
+                },
+                // every handle to it was dropped, nothing left to wait for
+                (None, _) => false,
+                _ => true,
             }
+        });
+
+        *self.loaded.entry(world_handle).or_default() += loaded;
+        for path in failed {
+            error!("failed to load asset {}", path);
+            self.failed.entry(world_handle).or_default().push(path);
+        }
+    }
+
+    // worlds nobody waits on keep requesting (chunk props, drops, weapons), their
+    // requests are sorted out here so the pending lists do not grow for ever
+    pub fn prune_requests(&mut self, server: &AssetServer) {
+        let idle: Vec<usize> = self.requested.keys()
+            .filter(|world_handle| !self.loading_worlds.contains(world_handle))
+            .copied()
+            .collect();
+        for world_handle in idle {
+            self.update_world(world_handle, server);
+        }
+    }
+
+    // releases every preloaded bundle
+    pub fn release_bundles(&mut self) {
+        let bundles: Vec<String> = self.bundles.keys().cloned().collect();
+        for bundle in bundles {
+            self.release_bundle(&bundle);
+        }
+    }
+
+    pub fn is_world_ready(&self, world_handle: usize) -> bool {
+        self.requested.get(&world_handle).is_none_or(|pending| pending.is_empty())
+    }
+
+    pub fn failed_assets(&self, world_handle: usize) -> &[String] {
+        self.failed.get(&world_handle).map_or(&[], |failed| failed.as_slice())
+    }
+
+    // share of the requests of every world the loading state waits on that are
+    // done loading, failed ones included
+    pub fn loading_progress(&self) -> f32 {
+        let mut pending = 0;
+        let mut done = 0;
+        for world_handle in self.loading_worlds.iter() {
+            pending += self.requested.get(world_handle).map_or(0, |pending| pending.len());
+            done += self.loaded.get(world_handle).copied().unwrap_or(0) + self.failed_assets(*world_handle).len();
+        }
+        if pending + done == 0 {
+            return 1.;
         }
+        done as f32 / (pending + done) as f32
     }
 
-    pub fn get_animation(&self) -> Handle<AnimationClip> {
-        self.animations[0].0.clone()
+    pub fn loading_failures(&self) -> Vec<&str> {
+        self.loading_worlds.iter()
+            .flat_map(|world_handle| self.failed_assets(*world_handle))
+            .map(|path| path.as_str())
+            .collect()
     }
+
 }
 
+#[derive(Resource)]
+struct LoadedState<S: States>(S);
+
+// Assets that failed in the loading state, kept once it is over so they can
+// be shown until the player dismisses them.
+#[derive(Default, Resource)]
+pub struct FailedAssets(pub Vec<String>);
+
+#[derive(Resource)]
+pub struct ManifestHandle(pub Handle<AssetManifest>);
+
+// bundles asked for by a state, requested as soon as the manifest is loaded
+#[derive(Default, Resource)]
+struct PendingBundles(Vec<&'static str>);
+
 pub struct AssetLoaderPlugin<S: States> {
     pub state: S,
+    pub next_state: S,
+    // manifest bundles to preload when entering each state
+    pub preload: Vec<(S, Vec<&'static str>)>,
 }
 impl<S: States> Plugin for AssetLoaderPlugin<S> {
     fn build(&self, app: &mut App){
         app
-        .init_resource::<GameAssets>();
+        .init_resource::<GameAssets>()
+        .init_resource::<PendingBundles>()
+        .init_resource::<FailedAssets>()
+        .init_asset::<AssetManifest>()
+        .register_asset_loader(RonAssetLoader::<AssetManifest>::new(&["assets.ron"]))
+        .insert_resource(LoadedState(self.next_state.clone()))
+        .add_systems(Startup, load_manifest)
+        .add_systems(Update, (request_bundles, poll_world_assets::<S>.run_if(in_state(self.state.clone())), prune_requests).chain())
+        .add_systems(Last, release_on_exit);
+
+        let mut expected_bundles: Vec<&'static str> = Vec::new();
+        for (state, bundles) in self.preload.iter() {
+            let bundles = bundles.clone();
+            expected_bundles.extend(bundles.iter());
+            app.add_systems(OnEnter(state.clone()), move |mut pending: ResMut<PendingBundles>| {
+                pending.0.extend(bundles.iter());
+            });
+        }
+        app.add_systems(Update, move |events: EventReader<AssetEvent<AssetManifest>>, manifests: Res<Assets<AssetManifest>>| {
+            validate_manifest(events, manifests, &expected_bundles);
+        });
     }
-}
\ No newline at end of file
+}
+
+fn load_manifest(
+    mut commands: Commands,
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+){
+    // the loading state holds until the manifest itself is in
+    let world_handle = assets.new_world();
+    let manifest = assets.request::<AssetManifest>(world_handle, MANIFEST_PATH, &server);
+    assets.load_world(world_handle);
+    commands.insert_resource(ManifestHandle(manifest));
+}
+
+fn request_bundles(
+    mut assets: ResMut<GameAssets>,
+    mut pending: ResMut<PendingBundles>,
+    server: Res<AssetServer>,
+    manifest: Option<Res<ManifestHandle>>,
+    manifests: Res<Assets<AssetManifest>>,
+){
+    if pending.0.is_empty() {
+        return;
+    }
+    let Some(manifest_handle) = manifest else {
+        return;
+    };
+    let Some(manifest) = manifests.get(&manifest_handle.0) else {
+        if server.get_load_state(&manifest_handle.0) == Some(LoadState::Failed) {
+            error!("no asset manifest, skipping bundles {:?}", pending.0);
+            pending.0.clear();
+        }
+        return;
+    };
+
+    for bundle in pending.0.drain(..) {
+        assets.load_bundle(manifest, bundle, &server);
+    }
+}
+
+fn prune_requests(
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+){
+    assets.prune_requests(&server);
+}
+
+fn release_on_exit(
+    mut exit: EventReader<AppExit>,
+    mut assets: ResMut<GameAssets>,
+){
+    if exit.read().count() > 0 {
+        assets.release_bundles();
+    }
+}
+
+fn validate_manifest(
+    mut events: EventReader<AssetEvent<AssetManifest>>,
+    manifests: Res<Assets<AssetManifest>>,
+    expected_bundles: &[&str],
+){
+    for event in events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } = event {
+            if let Some(manifest) = manifests.get(*id) {
+                if manifest.validate(expected_bundles) {
+                    info!("asset manifest: {} bundles validated", manifest.bundles.len());
+                }
+            }
+        }
+    }
+}
+
+fn poll_world_assets<S: States>(
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+    loaded_state: Res<LoadedState<S>>,
+    pending: Res<PendingBundles>,
+    mut failed_assets: ResMut<FailedAssets>,
+    mut next_state: ResMut<NextState<S>>,
+){
+    if assets.loading_worlds.is_empty() {
+        return;
+    }
+
+    let loading_worlds = assets.loading_worlds.clone();
+    for world_handle in loading_worlds.iter() {
+        assets.update_world(*world_handle, &server);
+    }
+
+    if pending.0.is_empty() && loading_worlds.iter().all(|world_handle| assets.is_world_ready(*world_handle)) {
+        let failed: Vec<String> = assets.loading_failures().into_iter().map(String::from).collect();
+        if !failed.is_empty() {
+            warn!("loading finished with {} failed assets: {}", failed.len(), failed.join(", "));
+        }
+        failed_assets.0.extend(failed);
+        assets.loading_worlds.clear();
+        next_state.set(loaded_state.0.clone());
+    }
+}
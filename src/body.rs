@@ -0,0 +1,124 @@
+use bevy::{prelude::*, render::view::RenderLayers};
+use bevy_rapier3d::control::KinematicCharacterControllerOutput;
+
+use crate::{animation::{AnimState, AnimationController, AnimationRoot, Clip}, asset_loader::GameAssets, camera::MainCamera, world::Player};
+
+const BODY_SCENE: &str = "models/test_runner.glb#Scene0";
+const BODY_RUN: &str = "models/test_runner.glb#Animation1";
+// the player's capsule is 2 units tall around its origin
+const FEET: f32 = -1.;
+// glTF models face +Z while look_to points -Z
+const MODEL_YAW: f32 = std::f32::consts::PI;
+// horizontal speeds past which the body walks, then runs
+const WALK_SPEED: f32 = 0.5;
+const RUN_SPEED: f32 = 5.;
+// the ground contact flickers on slopes, short hops keep the stride
+const FALL_DELAY: f32 = 0.15;
+
+// Only the TopCamera and the light see this layer, the MainCamera sits
+// inside the body.
+pub const BODY_LAYER: u8 = 1;
+
+// Runner model under the player, seen from the TopCamera and in shadows.
+#[derive(Component)]
+pub struct PlayerBody;
+
+pub struct PlayerBodyPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for PlayerBodyPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_systems(Update, (attach_body, layer_body_meshes).chain())
+        .add_systems(Update, animate_body.run_if(in_state(self.state.clone())));
+    }
+}
+
+// the runner only has a running cycle, standing still holds its stride
+fn body_animations() -> AnimationController {
+    AnimationController::default()
+    .with_clip(AnimState::Idle, Clip::new(BODY_RUN).speed(0.))
+    .with_clip(AnimState::Walk, Clip::new(BODY_RUN).scaled_by_velocity(4.))
+    .with_clip(AnimState::Run, Clip::new(BODY_RUN).speed(1.5).scaled_by_velocity(8.))
+    .with_clip(AnimState::Fall, Clip::new(BODY_RUN).speed(0.2).crossfade(0.3))
+    .with_state(AnimState::Idle)
+}
+
+fn attach_body(
+    mut commands: Commands,
+    players: Query<Entity, With<Player>>,
+    bodies: Query<(), With<PlayerBody>>,
+    assets: Res<GameAssets>,
+){
+    if !bodies.is_empty() {
+        return;
+    }
+    let (Ok(player), Some(scene)) = (players.get_single(), assets.get::<Scene>(BODY_SCENE)) else {
+        return;
+    };
+    commands.entity(player).with_children(|player| {
+        player.spawn((
+            PlayerBody,
+            AnimationRoot,
+            body_animations(),
+            SceneBundle {
+                scene,
+                transform: Transform::from_xyz(0., FEET, 0.),
+                ..default()
+            },
+        ));
+    });
+}
+
+// RenderLayers is not inherited, the meshes show up once the scene is spawned
+fn layer_body_meshes(
+    mut commands: Commands,
+    meshes: Query<Entity, Added<Handle<Mesh>>>,
+    parents: Query<&Parent>,
+    bodies: Query<(), With<PlayerBody>>,
+){
+    for mesh in meshes.iter() {
+        if parents.iter_ancestors(mesh).any(|ancestor| bodies.contains(ancestor)) {
+            commands.entity(mesh).insert(RenderLayers::layer(BODY_LAYER));
+        }
+    }
+}
+
+fn animate_body(
+    time: Res<Time>,
+    players: Query<Option<&KinematicCharacterControllerOutput>, With<Player>>,
+    cameras: Query<&Transform, (With<MainCamera>, Without<PlayerBody>)>,
+    mut bodies: Query<(&mut Transform, &mut AnimationController), With<PlayerBody>>,
+    mut airborne: Local<f32>,
+){
+    let (Ok(output), Ok(camera), Ok((mut transform, mut controller))) = (players.get_single(), cameras.get_single(), bodies.get_single_mut()) else {
+        return;
+    };
+    let dt = time.delta_seconds();
+
+    // there is no output until the controller first moves
+    let (grounded, moved) = output.map(|output| (output.grounded, output.effective_translation)).unwrap_or((true, Vec3::ZERO));
+    *airborne = if grounded { 0. } else { *airborne + dt };
+    let speed = if dt > 0. { Vec2::new(moved.x, moved.z).length() / dt } else { 0. };
+
+    controller.set_velocity(speed);
+    controller.set_state(if *airborne > FALL_DELAY {
+        AnimState::Fall
+    } else if speed > RUN_SPEED {
+        AnimState::Run
+    } else if speed > WALK_SPEED {
+        AnimState::Walk
+    } else {
+        AnimState::Idle
+    });
+
+    // the body follows the camera's yaw, never its pitch
+    let mut forward = *camera.forward();
+    forward.y = 0.;
+    if forward.length_squared() > f32::EPSILON {
+        transform.look_to(forward, Vec3::Y);
+        transform.rotate_y(MODEL_YAW);
+    }
+}
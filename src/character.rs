@@ -0,0 +1,277 @@
+use bevy::prelude::*;
+use bevy_rapier3d::{control::{CharacterAutostep, CharacterLength, KinematicCharacterController, KinematicCharacterControllerOutput}, dynamics::Velocity};
+
+use crate::inputs::gather_intents;
+
+#[derive(Resource, Clone, Debug)]
+pub struct CharacterSettings {
+    // horizontal, at full input the speed settles at acceleration / damping
+    pub acceleration: f32,
+    pub damping: f32,
+    // share of the acceleration left in the air
+    pub air_control: f32,
+    pub gravity: f32,
+    pub jump_velocity: f32,
+    // releasing the jump while rising multiplies the vertical velocity by this
+    pub jump_cut: f32,
+    // seconds after leaving the ground during which a jump still works
+    pub coyote_time: f32,
+    // seconds a jump pressed before landing is kept for the landing
+    pub jump_buffer: f32,
+    // degrees, steeper slopes cannot be climbed, and past the slide angle they slide
+    pub max_slope: f32,
+    pub min_slide_slope: f32,
+    pub step_height: f32,
+    // free space needed on top of a step to climb it
+    pub step_min_width: f32,
+    // keeps the character on the ground going down slopes and steps
+    pub snap_to_ground: f32,
+    // velocity added over the dash duration
+    pub dash_speed: f32,
+    pub dash_duration: f32,
+    pub dash_cooldown: f32,
+}
+
+impl Default for CharacterSettings {
+    fn default() -> Self {
+        CharacterSettings {
+            acceleration: 42.,
+            damping: 4.9,
+            air_control: 0.5,
+            gravity: 16.,
+            jump_velocity: 9.,
+            jump_cut: 0.5,
+            coyote_time: 0.12,
+            jump_buffer: 0.15,
+            max_slope: 45.,
+            min_slide_slope: 50.,
+            step_height: 0.4,
+            step_min_width: 0.2,
+            snap_to_ground: 0.3,
+            dash_speed: 50.,
+            dash_duration: 0.25,
+            dash_cooldown: 2.5,
+        }
+    }
+}
+
+// What the character is asked to do this frame, filled from the actions.
+#[derive(Component, Default)]
+pub struct CharacterIntent {
+    // horizontal, up to 1 for full speed
+    pub movement: Vec3,
+    pub jump: bool,
+    pub jump_held: bool,
+    pub dash: bool,
+}
+
+#[derive(Component, Default)]
+pub struct CharacterMotor {
+    pub grounded: bool,
+    since_grounded: f32,
+    // seconds left on a buffered jump
+    jump_buffer: f32,
+    // from the jump until the landing
+    jumping: bool,
+    jump_cut: bool,
+    since_dash: f32,
+    // direction and seconds left
+    dash: Option<(Vec3, f32)>,
+}
+
+pub struct CharacterPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for CharacterPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_resource::<CharacterSettings>()
+        .add_systems(Update, (configure_controllers, move_character.after(gather_intents)).chain().run_if(in_state(self.state.clone())));
+    }
+}
+
+fn configure_controllers(
+    settings: Res<CharacterSettings>,
+    mut controllers: Query<(&mut KinematicCharacterController, Ref<CharacterMotor>)>,
+){
+    for (mut controller, motor) in controllers.iter_mut() {
+        if !settings.is_changed() && !motor.is_added() {
+            continue;
+        }
+        controller.max_slope_climb_angle = settings.max_slope.to_radians();
+        controller.min_slope_slide_angle = settings.min_slide_slope.to_radians();
+        controller.autostep = Some(CharacterAutostep {
+            max_height: CharacterLength::Absolute(settings.step_height),
+            min_width: CharacterLength::Absolute(settings.step_min_width),
+            include_dynamic_bodies: false,
+        });
+        controller.snap_to_ground = Some(CharacterLength::Absolute(settings.snap_to_ground));
+    }
+}
+
+fn move_character(
+    time: Res<Time>,
+    settings: Res<CharacterSettings>,
+    mut characters: Query<(
+        &CharacterIntent,
+        &mut CharacterMotor,
+        &mut KinematicCharacterController,
+        &mut Velocity,
+        Option<&KinematicCharacterControllerOutput>,
+    )>,
+){
+    let dt = time.delta_seconds();
+
+    for (intent, mut motor, mut controller, mut velocity, output) in characters.iter_mut() {
+        // the output is the one of the previous move
+        let grounded = output.is_some_and(|output| output.grounded);
+        // a ceiling cut the last move short
+        let blocked_above = output.is_some_and(|output| {
+            output.desired_translation.y > 0. && output.effective_translation.y < output.desired_translation.y * 0.5
+        });
+        velocity.linvel = motor.step(&settings, intent, grounded, blocked_above, velocity.linvel, dt);
+        controller.translation = Some(velocity.linvel * dt);
+    }
+}
+
+impl CharacterMotor {
+
+    // one frame of movement, returns the new velocity
+    fn step(&mut self, settings: &CharacterSettings, intent: &CharacterIntent, grounded: bool, blocked_above: bool, mut linvel: Vec3, dt: f32) -> Vec3 {
+        self.grounded = grounded;
+        self.since_grounded = if self.grounded { 0. } else { self.since_grounded + dt };
+        self.since_dash += dt;
+        self.jump_buffer = if intent.jump { settings.jump_buffer } else { (self.jump_buffer - dt).max(0.) };
+
+        if self.grounded && linvel.y <= 0. {
+            self.jumping = false;
+            // the fall does not build up while standing
+            linvel.y = 0.;
+        }
+        // the rise ends at the ceiling instead of sticking to it until gravity wins
+        if blocked_above && linvel.y > 0. {
+            linvel.y = 0.;
+        }
+
+        if self.jump_buffer > 0. && !self.jumping && self.since_grounded <= settings.coyote_time {
+            linvel.y = settings.jump_velocity;
+            self.jumping = true;
+            self.jump_cut = false;
+            self.jump_buffer = 0.;
+        }
+        if self.jumping && !self.jump_cut && !intent.jump_held && linvel.y > 0. {
+            linvel.y *= settings.jump_cut;
+            self.jump_cut = true;
+        }
+
+        let control = if self.grounded { 1. } else { settings.air_control };
+        let mut horizontal = Vec3::new(linvel.x, 0., linvel.z);
+        horizontal += (intent.movement * settings.acceleration * control - horizontal * settings.damping) * dt;
+
+        if intent.dash && self.dash.is_none() && self.since_dash > settings.dash_cooldown {
+            let direction = intent.movement.normalize_or_zero();
+            if direction != Vec3::ZERO {
+                self.dash = Some((direction, settings.dash_duration));
+                self.since_dash = 0.;
+            }
+        }
+        if let Some((direction, remaining)) = self.dash {
+            let step = dt.min(remaining);
+            horizontal += direction * settings.dash_speed * step / settings.dash_duration.max(f32::EPSILON);
+            self.dash = (remaining - step > 0.).then_some((direction, remaining - step));
+        }
+
+        Vec3::new(horizontal.x, linvel.y - settings.gravity * dt, horizontal.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{CharacterIntent, CharacterMotor, CharacterSettings};
+
+    const DT: f32 = 1. / 60.;
+
+    fn idle() -> CharacterIntent {
+        CharacterIntent::default()
+    }
+
+    fn jump() -> CharacterIntent {
+        CharacterIntent { jump: true, jump_held: true, ..default() }
+    }
+
+    // stands for a frame, then walks off a ledge and falls for the given frames
+    fn falling_for(frames: u32, settings: &CharacterSettings) -> (CharacterMotor, Vec3) {
+        let mut motor = CharacterMotor::default();
+        let mut linvel = motor.step(settings, &idle(), true, false, Vec3::ZERO, DT);
+        for _ in 0..frames {
+            linvel = motor.step(settings, &idle(), false, false, linvel, DT);
+        }
+        (motor, linvel)
+    }
+
+    #[test]
+    fn jumps_within_coyote_time() {
+        let settings = CharacterSettings::default();
+        let frames = (settings.coyote_time / DT) as u32 - 1;
+        let (mut motor, linvel) = falling_for(frames, &settings);
+
+        let linvel = motor.step(&settings, &jump(), false, false, linvel, DT);
+        assert!(linvel.y > 0., "no jump {} frames after leaving the ground", frames);
+    }
+
+    #[test]
+    fn no_jump_after_coyote_time() {
+        let settings = CharacterSettings::default();
+        let frames = (settings.coyote_time / DT) as u32 + 2;
+        let (mut motor, linvel) = falling_for(frames, &settings);
+
+        let linvel = motor.step(&settings, &jump(), false, false, linvel, DT);
+        assert!(linvel.y < 0., "jumped {} frames after leaving the ground", frames);
+    }
+
+    // presses jump while falling, lands the given frames later
+    fn land_after_pressing(frames: u32, settings: &CharacterSettings) -> Vec3 {
+        let (mut motor, linvel) = falling_for(60, settings);
+        let mut linvel = motor.step(settings, &jump(), false, false, linvel, DT);
+        for _ in 0..frames {
+            linvel = motor.step(settings, &idle(), false, false, linvel, DT);
+        }
+        motor.step(settings, &idle(), true, false, linvel.min(Vec3::ZERO), DT)
+    }
+
+    #[test]
+    fn buffered_jump_fires_on_landing() {
+        let settings = CharacterSettings::default();
+        let frames = (settings.jump_buffer / DT) as u32 - 2;
+        assert!(land_after_pressing(frames, &settings).y > 0.);
+    }
+
+    #[test]
+    fn buffered_jump_expires() {
+        let settings = CharacterSettings::default();
+        let frames = (settings.jump_buffer / DT) as u32 + 2;
+        assert!(land_after_pressing(frames, &settings).y <= 0.);
+    }
+
+    #[test]
+    fn only_one_jump_until_landing() {
+        let settings = CharacterSettings::default();
+        let mut motor = CharacterMotor::default();
+        let linvel = motor.step(&settings, &jump(), true, false, Vec3::ZERO, DT);
+        let again = motor.step(&settings, &jump(), false, false, linvel, DT);
+        assert!(again.y < linvel.y);
+    }
+
+    #[test]
+    fn ceiling_stops_the_rise() {
+        let settings = CharacterSettings::default();
+        let mut motor = CharacterMotor::default();
+        let linvel = motor.step(&settings, &jump(), true, false, Vec3::ZERO, DT);
+        let linvel = motor.step(&settings, &CharacterIntent { jump_held: true, ..default() }, false, true, linvel, DT);
+        assert!(linvel.y <= 0.);
+    }
+}
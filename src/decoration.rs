@@ -0,0 +1,274 @@
+use bevy::prelude::*;
+use bevy_rapier3d::geometry::Collider;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::ron_loader::RonAssetLoader;
+
+const CATALOGUE_PATH: &str = "decorations/cave.decorations.ron";
+
+pub struct DecorationPlugin;
+impl Plugin for DecorationPlugin {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_asset::<DecorationCatalogue>()
+        .register_asset_loader(RonAssetLoader::<DecorationCatalogue>::new(&["decorations.ron"]).with_validation(DecorationCatalogue::validate))
+        .add_systems(Startup, load_catalogue);
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DecorationSettings {
+    // props per 100x100 units of ground
+    pub density: f32,
+    pub min_spacing: f32,
+    // dart throwing gives up after this many rejected candidates per point
+    pub max_attempts: u32,
+}
+
+impl Default for DecorationSettings {
+    fn default() -> Self {
+        DecorationSettings {
+            density: 4.,
+            min_spacing: 8.,
+            max_attempts: 30,
+        }
+    }
+}
+
+impl DecorationSettings {
+
+    pub fn count_for(&self, size: f32) -> usize {
+        (self.density * size * size / 10_000.).round() as usize
+    }
+}
+
+#[derive(Component)]
+pub struct Decoration;
+
+#[derive(Resource)]
+pub struct CatalogueHandle(pub Handle<DecorationCatalogue>);
+
+#[derive(Asset, TypePath, Deserialize)]
+pub struct DecorationCatalogue {
+    pub props: Vec<DecorationProp>,
+}
+
+#[derive(Deserialize)]
+pub struct DecorationProp {
+    pub name: String,
+    pub scene: String,
+    pub collider: Option<ColliderShape>,
+    #[serde(default)]
+    pub collider_offset: (f32, f32, f32),
+    #[serde(default = "default_scale")]
+    pub scale: (f32, f32),
+    #[serde(default = "default_yaw")]
+    pub random_yaw: bool,
+    // max tilt away from the vertical, in degrees
+    #[serde(default)]
+    pub max_tilt: f32,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_scale() -> (f32, f32) { (1., 1.) }
+fn default_yaw() -> bool { true }
+fn default_weight() -> f32 { 1. }
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum ColliderShape {
+    Ball { radius: f32 },
+    Cuboid { half_extents: (f32, f32, f32) },
+    Cone { half_height: f32, radius: f32 },
+    Cylinder { half_height: f32, radius: f32 },
+    Capsule { half_height: f32, radius: f32 },
+}
+
+impl ColliderShape {
+    pub fn collider(&self) -> Collider {
+        match *self {
+            ColliderShape::Ball { radius } => Collider::ball(radius),
+            ColliderShape::Cuboid { half_extents: (x, y, z) } => Collider::cuboid(x, y, z),
+            ColliderShape::Cone { half_height, radius } => Collider::cone(half_height, radius),
+            ColliderShape::Cylinder { half_height, radius } => Collider::cylinder(half_height, radius),
+            ColliderShape::Capsule { half_height, radius } => Collider::capsule_y(half_height, radius),
+        }
+    }
+}
+
+impl DecorationCatalogue {
+
+    // a bad prop rejects the whole catalogue, the errors name it
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |value: f32| value.is_finite() && value > 0.;
+        let non_negative = |value: f32| value.is_finite() && value >= 0.;
+
+        for (i, prop) in self.props.iter().enumerate() {
+            if self.props[..i].iter().any(|other| other.name == prop.name) {
+                return Err(format!("prop {} is defined twice", prop.name));
+            }
+            if prop.scene.is_empty() {
+                return Err(format!("prop {} has no scene", prop.name));
+            }
+            if !non_negative(prop.weight) {
+                return Err(format!("prop {}: weight cannot be negative, got {}", prop.name, prop.weight));
+            }
+            let (min, max) = prop.scale;
+            if !positive(min) || !positive(max) || min > max {
+                return Err(format!("prop {}: scale must be positive and (min, max), got {:?}", prop.name, prop.scale));
+            }
+            if !(0. ..=90.).contains(&prop.max_tilt) {
+                return Err(format!("prop {}: max_tilt must be between 0 and 90 degrees, got {}", prop.name, prop.max_tilt));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pick(&self, rng: &mut impl Rng) -> Option<&DecorationProp> {
+        let total: f32 = self.props.iter().map(|prop| prop.weight.max(0.)).sum();
+        if total <= 0. {
+            return None;
+        }
+
+        let mut roll = rng.gen_range(0.0..total);
+        for prop in self.props.iter() {
+            roll -= prop.weight.max(0.);
+            if roll < 0. {
+                return Some(prop);
+            }
+        }
+        self.props.last()
+    }
+}
+
+impl DecorationProp {
+
+    // transform relative to the chunk, `ground` being the point on the terrain
+    pub fn transform(&self, ground: Vec3, rng: &mut impl Rng) -> Transform {
+        let mut transform = Transform::from_translation(ground);
+
+        let (min, max) = self.scale;
+        if max > min {
+            transform.scale = Vec3::splat(rng.gen_range(min..max));
+        } else {
+            transform.scale = Vec3::splat(min);
+        }
+
+        if self.max_tilt > 0. {
+            let axis = Vec3::new(rng.gen_range(-1.0..1.0), 0., rng.gen_range(-1.0..1.0)).normalize_or_zero();
+            if axis != Vec3::ZERO {
+                transform.rotate(Quat::from_axis_angle(axis, rng.gen_range(0.0..self.max_tilt).to_radians()));
+            }
+        }
+
+        if self.random_yaw {
+            transform.rotate_y(rng.gen_range(0.0..std::f32::consts::TAU));
+        }
+        transform
+    }
+
+    pub fn spawn(&self, parent: &mut ChildBuilder, scene: Handle<Scene>, transform: Transform) {
+        parent.spawn((Decoration, SceneBundle{
+            scene,
+            transform,
+            ..default()
+        })).
+        with_children(|prop|{
+
+            if let Some(shape) = self.collider {
+                let (x, y, z) = self.collider_offset;
+                prop.spawn(TransformBundle{
+                    local: Transform::from_xyz(x, y, z),
+                    ..default()
+                }).insert(shape.collider());
+            }
+        });
+    }
+}
+
+fn load_catalogue(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+){
+    commands.insert_resource(CatalogueHandle(server.load(CATALOGUE_PATH)));
+}
+
+// Poisson-disk style sampling of a size x size square in chunk space.
+// Points keep min_spacing / 2 away from the edges, so props of two neighbouring
+// chunks are still min_spacing apart without having to know about each other.
+pub fn scatter_points(rng: &mut impl Rng, size: f32, settings: &DecorationSettings) -> Vec<Vec2> {
+    let margin = settings.min_spacing / 2.;
+    let count = settings.count_for(size);
+    let mut points: Vec<Vec2> = Vec::with_capacity(count);
+
+    if size <= 2. * margin {
+        return points;
+    }
+
+    let spacing_squared = settings.min_spacing * settings.min_spacing;
+    let mut attempts = 0;
+    while points.len() < count && attempts < settings.max_attempts * count as u32 {
+        attempts += 1;
+        let candidate = Vec2::new(
+            rng.gen_range(margin..size - margin),
+            rng.gen_range(margin..size - margin),
+        );
+
+        if points.iter().all(|point| point.distance_squared(candidate) >= spacing_squared) {
+            points.push(candidate);
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{scatter_points, DecorationSettings};
+
+    const SIZE: f32 = 50.;
+
+    #[test]
+    fn points_keep_the_minimum_spacing() {
+        let settings = DecorationSettings { density: 40., ..default() };
+        for seed in 0..20 {
+            let points = scatter_points(&mut StdRng::seed_from_u64(seed), SIZE, &settings);
+            assert!(!points.is_empty());
+            for (i, a) in points.iter().enumerate() {
+                for b in points.iter().skip(i + 1) {
+                    assert!(a.distance(*b) >= settings.min_spacing, "{} and {} too close", a, b);
+                }
+            }
+        }
+    }
+
+    // half the spacing on each side of a chunk border adds up to the full spacing
+    #[test]
+    fn points_keep_half_the_spacing_from_the_edges() {
+        let settings = DecorationSettings::default();
+        let margin = settings.min_spacing / 2.;
+        let points = scatter_points(&mut StdRng::seed_from_u64(1), SIZE, &settings);
+        for point in points {
+            assert!(point.min_element() >= margin && point.max_element() <= SIZE - margin, "{} on the border", point);
+        }
+    }
+
+    #[test]
+    fn same_rng_same_points() {
+        let settings = DecorationSettings::default();
+        let a = scatter_points(&mut StdRng::seed_from_u64(9), SIZE, &settings);
+        let b = scatter_points(&mut StdRng::seed_from_u64(9), SIZE, &settings);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn no_points_on_chunks_smaller_than_the_spacing() {
+        let settings = DecorationSettings::default();
+        assert!(scatter_points(&mut StdRng::seed_from_u64(0), settings.min_spacing, &settings).is_empty());
+    }
+}
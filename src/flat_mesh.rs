@@ -1,34 +1,66 @@
-use bevy::{prelude::*, render::{mesh::{Indices, PrimitiveTopology}, render_asset::RenderAssetUsages}};
+use bevy::{prelude::*, render::{mesh::{Indices, PrimitiveTopology, VertexAttributeValues}, render_asset::RenderAssetUsages}};
+use bevy_rapier3d::geometry::Collider;
 
-pub fn gen_flat_mesh(left: i32, top: i32, width: i32, height: i32) -> Mesh{
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridLayout {
+    // 4 vertices per cell, every unit shows the whole texture (pixel-art tile
+    // look). Coarser lods stretch their cells over several tiles, so the
+    // texture needs a repeating sampler there too.
+    PerCell,
+    // one vertex per grid corner shared by the neighbouring cells. The texture
+    // repeats every `repeat` units; uv_offset is added to the vertex position
+    // first, pass the mesh origin to get world-space uvs that line up across meshes.
+    // Tiling uvs go past 1, so the texture needs a repeating sampler.
+    Shared { repeat: f32, uv_offset: Vec2 },
+}
+
+pub fn gen_grid_mesh(left: i32, top: i32, width: i32, height: i32, lod: u32, layout: GridLayout) -> Mesh{
+    match layout {
+        GridLayout::PerCell => gen_flat_mesh_lod(left, top, width, height, lod),
+        GridLayout::Shared { repeat, uv_offset } => gen_shared_grid_mesh(left, top, width, height, lod, repeat, uv_offset),
+    }
+}
 
-    let vertices_count = (width * height * 4) as usize;
-    let indices_count = (width * height * 6) as usize;
+// Each level of detail doubles the cell size, lod 0 being one cell per unit.
+// The last row and column of cells are clamped so the mesh keeps its size.
+pub fn gen_flat_mesh_lod(left: i32, top: i32, width: i32, height: i32, lod: u32) -> Mesh{
+
+    let cell = 1 << lod;
+    let cells_w = (width + cell - 1) / cell;
+    let cells_h = (height + cell - 1) / cell;
+
+    let vertices_count = (cells_w * cells_h * 4) as usize;
+    let indices_count = (cells_w * cells_h * 6) as usize;
     let mut positions = std::vec![Vec3::ZERO; vertices_count];
     let mut uvs = std::vec![Vec2::ZERO; vertices_count];
     let normals = std::vec![Vec3::Y; vertices_count];
 
     let mut indices: Vec<u32> = std::vec![0; indices_count];
 
-    for h in 0..(height){
-        for w in 0..(width){
-            let position_first = ((h * width + w) * 4) as usize;
-            let w: i32 = w as i32;
-            let h: i32 = h as i32;
+    for h in 0..(cells_h){
+        for w in 0..(cells_w){
+            let position_first = ((h * cells_w + w) * 4) as usize;
+            let x0 = left + w * cell;
+            let x1 = left + ((w + 1) * cell).min(width);
+            let z0 = top + h * cell;
+            let z1 = top + ((h + 1) * cell).min(height);
             
-            positions[position_first] = Vec3 {x: (left + w) as f32, y: 0., z: (top + h) as f32};
-            positions[position_first + 1] = Vec3 {x: (left + w + 1) as f32, y: 0., z: (top + h) as f32};
-            positions[position_first + 2] = Vec3 {x: (left + w) as f32, y: 0., z: (top + h + 1) as f32};
-            positions[position_first + 3] = Vec3 {x: (left + w + 1) as f32, y: 0., z: (top + h + 1) as f32};
+            positions[position_first] = Vec3 {x: x0 as f32, y: 0., z: z0 as f32};
+            positions[position_first + 1] = Vec3 {x: x1 as f32, y: 0., z: z0 as f32};
+            positions[position_first + 2] = Vec3 {x: x0 as f32, y: 0., z: z1 as f32};
+            positions[position_first + 3] = Vec3 {x: x1 as f32, y: 0., z: z1 as f32};
 
 
+            // one tile per unit whatever the lod
+            let u = (x1 - x0) as f32;
+            let v = (z1 - z0) as f32;
             uvs[position_first] = Vec2 {x: 0.0, y: 0.0};
-            uvs[position_first + 1] = Vec2 {x: 1.0, y: 0.0};
-            uvs[position_first + 2] = Vec2 {x: 0.0, y: 1.0};
-            uvs[position_first + 3] = Vec2 {x: 1.0, y: 1.0};
+            uvs[position_first + 1] = Vec2 {x: u, y: 0.0};
+            uvs[position_first + 2] = Vec2 {x: 0.0, y: v};
+            uvs[position_first + 3] = Vec2 {x: u, y: v};
 
 
-            let indice_first = ((h * (width as i32) + w) * 6) as usize;
+            let indice_first = ((h * cells_w + w) * 6) as usize;
             let position_first: u32 = position_first as u32;
 
             indices[indice_first] = position_first;
@@ -41,10 +73,51 @@ pub fn gen_flat_mesh(left: i32, top: i32, width: i32, height: i32) -> Mesh{
         }
     }
 
+    build_mesh(positions, uvs, normals, indices)
+}
 
+// (cells_w + 1) * (cells_h + 1) vertices instead of 4 per cell
+pub fn gen_shared_grid_mesh(left: i32, top: i32, width: i32, height: i32, lod: u32, repeat: f32, uv_offset: Vec2) -> Mesh{
+
+    let cell = 1 << lod;
+    let cells_w = (width + cell - 1) / cell;
+    let cells_h = (height + cell - 1) / cell;
+    let row = cells_w + 1;
+
+    let vertices_count = (row * (cells_h + 1)) as usize;
+    let indices_count = (cells_w * cells_h * 6) as usize;
+    let mut positions = Vec::with_capacity(vertices_count);
+    let mut uvs = Vec::with_capacity(vertices_count);
+    let normals = std::vec![Vec3::Y; vertices_count];
+
+    let mut indices: Vec<u32> = Vec::with_capacity(indices_count);
+
+    for h in 0..=cells_h {
+        for w in 0..=cells_w {
+            let x = left + (w * cell).min(width);
+            let z = top + (h * cell).min(height);
+            positions.push(Vec3 {x: x as f32, y: 0., z: z as f32});
+            uvs.push((Vec2::new(x as f32, z as f32) + uv_offset) / repeat);
+        }
+    }
+
+    for h in 0..cells_h {
+        for w in 0..cells_w {
+            let first = (h * row + w) as u32;
+            let below = first + row as u32;
+
+            indices.extend_from_slice(&[first, below, first + 1]);
+            indices.extend_from_slice(&[first + 1, below, below + 1]);
+        }
+    }
+
+    build_mesh(positions, uvs, normals, indices)
+}
+
+fn build_mesh(positions: Vec<Vec3>, uvs: Vec<Vec2>, normals: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
     Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
-        // Add 4 vertices, each with its own position attribute (coordinate in
-        // 3D space), for each of the corners of the parallelogram.
+        // Add the vertices, each with its own position attribute (coordinate in
+        // 3D space), for the corners of every cell.
         .with_inserted_attribute(
             Mesh::ATTRIBUTE_POSITION,
             positions
@@ -63,3 +136,123 @@ pub fn gen_flat_mesh(left: i32, top: i32, width: i32, height: i32) -> Mesh{
         // indices of the vertices that make it up in a counter-clockwise order.
         .with_inserted_indices(Indices::U32(indices))
 }
+
+// Skirt depth per unit of cell size, enough for the terrain amplitude to stay
+// within it between two vertices of the coarser neighbour.
+const SKIRT_DEPTH: f32 = 1.;
+
+// Same grid as gen_grid_mesh, with every vertex lifted to the height from
+// sample(x, z), which also gives the normal there. It is sampled in mesh space:
+// offset it by the chunk origin so two chunks compute the exact same values on
+// their shared edge.
+// Neighbours at different lods still disagree between the coarse vertices, a
+// skirt hanging below every edge hides those cracks.
+pub fn gen_terrain_mesh(
+    left: i32, top: i32, width: i32, height: i32, lod: u32, layout: GridLayout,
+    sample: impl Fn(f32, f32) -> (f32, Vec3),
+) -> Mesh {
+    let mut mesh = gen_grid_mesh(left, top, width, height, lod, layout);
+
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+        return mesh;
+    };
+
+    let mut normals = Vec::with_capacity(positions.len());
+    for position in positions.iter_mut() {
+        let (y, normal) = sample(position[0], position[2]);
+        position[1] = y;
+        normals.push(normal);
+    }
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+
+    let cell = 1 << lod;
+    let uv_scale = match layout {
+        GridLayout::PerCell => 1.,
+        GridLayout::Shared { repeat, .. } => repeat,
+    };
+    let xs = edge_steps(left, width, cell);
+    let zs = edge_steps(top, height, cell);
+    add_skirts(&mut mesh, &xs, &zs, SKIRT_DEPTH * cell as f32, uv_scale, &sample);
+    mesh
+}
+
+// x (or z) of the vertices along one side of the grid
+fn edge_steps(start: i32, length: i32, cell: i32) -> Vec<i32> {
+    let cells = (length + cell - 1) / cell;
+    (0..=cells).map(|step| start + (step * cell).min(length)).collect()
+}
+
+// xs and zs are the vertices along the sides, from edge_steps
+fn add_skirts(
+    mesh: &mut Mesh, xs: &[i32], zs: &[i32], depth: f32, uv_scale: f32,
+    sample: &impl Fn(f32, f32) -> (f32, Vec3),
+) {
+    let (Some(&left), Some(&right), Some(&top), Some(&bottom)) = (xs.first(), xs.last(), zs.first(), zs.last()) else {
+        return;
+    };
+    let edges: [Vec<(i32, i32)>; 4] = [
+        xs.iter().map(|&x| (x, top)).collect(),
+        xs.iter().map(|&x| (x, bottom)).collect(),
+        zs.iter().map(|&z| (left, z)).collect(),
+        zs.iter().map(|&z| (right, z)).collect(),
+    ];
+
+    let first = mesh.count_vertices() as u32;
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for edge in edges.iter() {
+        for pair in edge.windows(2) {
+            let a = Vec2::new(pair[0].0 as f32, pair[0].1 as f32);
+            let b = Vec2::new(pair[1].0 as f32, pair[1].1 as f32);
+            let length = a.distance(b) / uv_scale;
+            let index = first + positions.len() as u32;
+
+            for (corner, u) in [(a, 0.), (b, length)] {
+                let (y, normal) = sample(corner.x, corner.y);
+                let normal = normal.to_array();
+                positions.push([corner.x, y, corner.y]);
+                positions.push([corner.x, y - depth, corner.y]);
+                normals.extend([normal, normal]);
+                uvs.extend([[u, 0.], [u, depth / uv_scale]]);
+            }
+
+            // both windings, the skirt is seen from either chunk
+            indices.extend_from_slice(&[index, index + 1, index + 2, index + 2, index + 1, index + 3]);
+            indices.extend_from_slice(&[index, index + 2, index + 1, index + 2, index + 3, index + 1]);
+        }
+    }
+
+    if let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
+        values.extend(positions);
+    }
+    if let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
+        values.extend(normals);
+    }
+    if let Some(VertexAttributeValues::Float32x2(values)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+        values.extend(uvs);
+    }
+    if let Some(Indices::U32(values)) = mesh.indices_mut() {
+        values.extend(indices);
+    }
+}
+
+// Rapier heightfield covering the same area as gen_terrain_mesh(0, 0, width, height, ..).
+// The collider is centered on its transform, so place it at (width / 2, 0, height / 2).
+pub fn gen_terrain_collider(width: i32, height: i32, height_at: impl Fn(f32, f32) -> f32) -> Collider {
+    let rows = (height + 1) as usize;
+    let cols = (width + 1) as usize;
+
+    // column major: rows run along z, columns along x
+    let mut heights = Vec::with_capacity(rows * cols);
+    for x in 0..cols {
+        for z in 0..rows {
+            heights.push(height_at(x as f32, z as f32));
+        }
+    }
+
+    Collider::heightfield(heights, rows, cols, Vec3::new(width as f32, 1., height as f32))
+}
@@ -0,0 +1,273 @@
+use std::collections::BinaryHeap;
+
+use bevy::{prelude::*, utils::{Duration, Instant}};
+
+use crate::{navigation::{NavGrid, NavSettings, OpenCell}, steering::steer_mobs, world::Player};
+
+const DIRECTIONS: [IVec2; 8] = [
+    IVec2::new(1, 0), IVec2::new(-1, 0), IVec2::new(0, 1), IVec2::new(0, -1),
+    IVec2::new(1, 1), IVec2::new(1, -1), IVec2::new(-1, 1), IVec2::new(-1, -1),
+];
+const NO_DIRECTION: u8 = u8::MAX;
+
+#[derive(Resource)]
+pub struct FlowSettings {
+    // half size of the square the field covers around the player, in cells.
+    // Mobs further than the spawner's sleep radius do not move anyway
+    pub radius: i32,
+    // seconds, the player crossing cells quickly does not rebuild every frame
+    pub min_interval: f32,
+}
+
+impl Default for FlowSettings {
+    fn default() -> Self {
+        FlowSettings {
+            radius: 64,
+            min_interval: 0.1,
+        }
+    }
+}
+
+// Shared field of directions toward the player, one per nav cell. Every mob
+// reads its own cell instead of searching a path.
+#[derive(Resource, Default)]
+pub struct FlowField {
+    origin: IVec2,
+    size: i32,
+    cell_size: f32,
+    goal: Option<IVec2>,
+    grid_version: u32,
+    directions: Vec<u8>,
+    since_build: f32,
+    pub build_time: Duration,
+}
+
+impl FlowField {
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        let local = cell - self.origin;
+        if local.x < 0 || local.y < 0 || local.x >= self.size || local.y >= self.size {
+            return None;
+        }
+        Some((local.y * self.size + local.x) as usize)
+    }
+
+    // none outside the field, on blocked cells, and on the goal itself
+    pub fn direction_at(&self, position: Vec3) -> Option<Vec3> {
+        if self.cell_size <= 0. {
+            return None;
+        }
+        let cell = IVec2::new((position.x / self.cell_size).floor() as i32, (position.z / self.cell_size).floor() as i32);
+        let direction = *self.directions.get(self.index(cell)?)?;
+        if direction == NO_DIRECTION {
+            return None;
+        }
+        let step = DIRECTIONS[direction as usize];
+        Some(Vec3::new(step.x as f32, 0., step.y as f32).normalize())
+    }
+
+    // Dijkstra from the goal over a copy of the grid window, then every cell
+    // points at its cheapest neighbour
+    fn build(&mut self, grid: &NavGrid, goal: IVec2, radius: i32, cell_size: f32, max_step: f32) {
+        self.size = radius * 2 + 1;
+        self.origin = goal - IVec2::splat(radius);
+        self.cell_size = cell_size;
+        self.goal = Some(goal);
+        self.grid_version = grid.version();
+
+        let size = self.size;
+        let count = (size * size) as usize;
+        // the chunk lookups are the slow part, do them once
+        let mut walkable = vec![false; count];
+        let mut height = vec![0.; count];
+        for z in 0..size {
+            for x in 0..size {
+                let cell = self.origin + IVec2::new(x, z);
+                let i = (z * size + x) as usize;
+                walkable[i] = grid.is_walkable(cell);
+                height[i] = grid.height(cell).unwrap_or_default();
+            }
+        }
+
+        let passable = |from: IVec2, step: IVec2| -> Option<(usize, f32)> {
+            let to = from + step;
+            if to.x < 0 || to.y < 0 || to.x >= size || to.y >= size {
+                return None;
+            }
+            let at = |cell: IVec2| (cell.y * size + cell.x) as usize;
+            if !walkable[at(to)] || (height[at(to)] - height[at(from)]).abs() > max_step {
+                return None;
+            }
+            if step.x != 0 && step.y != 0 {
+                if !walkable[at(from + IVec2::new(step.x, 0))] || !walkable[at(from + IVec2::new(0, step.y))] {
+                    return None;
+                }
+                return Some((at(to), std::f32::consts::SQRT_2));
+            }
+            Some((at(to), 1.))
+        };
+
+        self.directions.clear();
+        self.directions.resize(count, NO_DIRECTION);
+        let Some(start) = grid.nearest_walkable(goal, 2) else {
+            return;
+        };
+
+        let mut distance = vec![f32::INFINITY; count];
+        let mut open = BinaryHeap::new();
+        let start = start - self.origin;
+        distance[(start.y * size + start.x) as usize] = 0.;
+        open.push(OpenCell { score: 0., cell: start });
+
+        while let Some(OpenCell { score, cell }) = open.pop() {
+            if score > distance[(cell.y * size + cell.x) as usize] {
+                continue;
+            }
+            for step in DIRECTIONS {
+                let Some((next, cost)) = passable(cell, step) else {
+                    continue;
+                };
+                if score + cost < distance[next] {
+                    distance[next] = score + cost;
+                    open.push(OpenCell { score: score + cost, cell: cell + step });
+                }
+            }
+        }
+
+        for z in 0..size {
+            for x in 0..size {
+                let cell = IVec2::new(x, z);
+                let i = (z * size + x) as usize;
+                if !distance[i].is_finite() || distance[i] == 0. {
+                    continue;
+                }
+                let mut best = (distance[i], NO_DIRECTION);
+                for (direction, step) in DIRECTIONS.iter().enumerate() {
+                    if let Some((next, _)) = passable(cell, *step) {
+                        if distance[next] < best.0 {
+                            best = (distance[next], direction as u8);
+                        }
+                    }
+                }
+                self.directions[i] = best.1;
+            }
+        }
+    }
+}
+
+pub struct FlowFieldPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for FlowFieldPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_resource::<FlowSettings>()
+        .init_resource::<FlowField>()
+        .add_systems(Update, update_flow_field.before(steer_mobs).run_if(in_state(self.state.clone())));
+    }
+}
+
+// rebuilt when the player changes cell or chunks got baked or dropped
+fn update_flow_field(
+    time: Res<Time>,
+    mut field: ResMut<FlowField>,
+    grid: Res<NavGrid>,
+    nav_settings: Res<NavSettings>,
+    settings: Res<FlowSettings>,
+    player: Query<&Transform, With<Player>>,
+){
+    let Ok(player_transform) = player.get_single() else {
+        return;
+    };
+    field.since_build += time.delta_seconds();
+
+    let goal = grid.cell_of(player_transform.translation);
+    let stale = field.goal != Some(goal) || field.grid_version != grid.version();
+    if !stale || field.since_build < settings.min_interval {
+        return;
+    }
+
+    let start = Instant::now();
+    field.build(&grid, goal, settings.radius, nav_settings.cell_size, nav_settings.max_step);
+    field.build_time = start.elapsed();
+    field.since_build = 0.;
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::FlowField;
+    use crate::navigation::NavGrid;
+
+    const GOAL: IVec2 = IVec2::new(16, 16);
+    const RADIUS: i32 = 8;
+    const MAX_STEP: f32 = 0.5;
+
+    fn center(cell: IVec2) -> Vec3 {
+        Vec3::new(cell.x as f32 + 0.5, 0., cell.y as f32 + 0.5)
+    }
+
+    fn field(grid: &NavGrid) -> FlowField {
+        let mut field = FlowField::default();
+        field.build(grid, GOAL, RADIUS, 1., MAX_STEP);
+        field
+    }
+
+    // follows the field from a cell, None if it stops before the goal
+    fn walk(field: &FlowField, grid: &NavGrid, mut cell: IVec2) -> Option<usize> {
+        for steps in 0..100 {
+            if cell == GOAL {
+                return Some(steps);
+            }
+            assert!(grid.is_walkable(cell), "the field leads onto {}", cell);
+            let direction = field.direction_at(center(cell))?;
+            cell += IVec2::new(direction.x.round() as i32, direction.z.round() as i32);
+        }
+        None
+    }
+
+    #[test]
+    fn points_straight_at_the_goal_on_open_ground() {
+        let grid = NavGrid::flat(1., 32, [(0, 0)], &[]);
+        let field = field(&grid);
+
+        let east = field.direction_at(center(GOAL + IVec2::new(4, 0))).unwrap();
+        assert!(east.abs_diff_eq(Vec3::NEG_X, 1e-5), "{}", east);
+        let diagonal = field.direction_at(center(GOAL + IVec2::new(-3, -3))).unwrap();
+        assert!(diagonal.abs_diff_eq(Vec3::new(1., 0., 1.).normalize(), 1e-5), "{}", diagonal);
+
+        assert_eq!(field.direction_at(center(GOAL)), None);
+        assert_eq!(field.direction_at(center(GOAL + IVec2::splat(RADIUS + 1))), None);
+    }
+
+    #[test]
+    fn leads_around_a_wall() {
+        let wall: Vec<IVec2> = (10..=22).map(|z| IVec2::new(18, z)).collect();
+        let grid = NavGrid::flat(1., 32, [(0, 0)], &wall);
+        let field = field(&grid);
+
+        assert_eq!(field.direction_at(center(IVec2::new(18, 16))), None);
+        let steps = walk(&field, &grid, IVec2::new(21, 16)).expect("the field does not reach the goal");
+        // around the end of the wall rather than the 5 cells through it
+        assert!(steps > 5, "{} steps", steps);
+    }
+
+    #[test]
+    fn every_reachable_cell_reaches_the_goal() {
+        let wall: Vec<IVec2> = (12..=20).map(|x| IVec2::new(x, 12)).collect();
+        let grid = NavGrid::flat(1., 32, [(0, 0)], &wall);
+        let field = field(&grid);
+
+        for z in GOAL.y - RADIUS..=GOAL.y + RADIUS {
+            for x in GOAL.x - RADIUS..=GOAL.x + RADIUS {
+                let cell = IVec2::new(x, z);
+                if grid.is_walkable(cell) {
+                    assert!(walk(&field, &grid, cell).is_some(), "stuck from {}", cell);
+                }
+            }
+        }
+    }
+}
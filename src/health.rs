@@ -0,0 +1,209 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_rapier3d::dynamics::ExternalImpulse;
+
+use crate::{asset_loader::GameAssets, world::Player};
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    // seconds during which further hits are ignored after one lands
+    pub invulnerability: f32,
+}
+
+impl Health {
+
+    pub fn new(max: f32) -> Self {
+        Health { current: max, max, invulnerability: 0. }
+    }
+
+    pub fn with_invulnerability(mut self, seconds: f32) -> Self {
+        self.invulnerability = seconds;
+        self
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.max <= 0. { 0. } else { (self.current / self.max).clamp(0., 1.) }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.
+    }
+}
+
+// What happens to the entity once it dies, entities without it just stay
+// there dead (the player, the game over screen decides).
+#[derive(Component, Clone, Debug, Default)]
+pub struct Mortal {
+    pub despawn_after: Option<f32>,
+    // scene path spawned where the entity died
+    pub drop: Option<String>,
+}
+
+#[derive(Component)]
+pub struct Invulnerable(pub Timer);
+
+#[derive(Component)]
+struct Despawning(Timer);
+
+// Ask for damage to be dealt, ignored on dead or invulnerable targets.
+#[derive(Event, Clone, Debug)]
+pub struct Damage {
+    pub target: Entity,
+    pub amount: f32,
+    pub source: Option<Entity>,
+    // applied to the target's ExternalImpulse if it has one
+    pub impulse: Vec3,
+}
+
+// Damage that was actually taken.
+#[derive(Event, Clone, Debug)]
+pub struct Damaged {
+    pub target: Entity,
+    pub amount: f32,
+    pub remaining: f32,
+    pub source: Option<Entity>,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct Died {
+    pub entity: Entity,
+    pub source: Option<Entity>,
+    pub position: Vec3,
+}
+
+// What the player has dealt so far, for the UI to show.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Score {
+    pub kills: u32,
+    pub damage_dealt: f32,
+}
+
+pub struct HealthPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for HealthPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_event::<Damage>()
+        .add_event::<Damaged>()
+        .add_event::<Died>()
+        .init_resource::<Score>()
+        .add_systems(Update, (tick_invulnerability, apply_damage, handle_deaths, despawn_dead).chain().run_if(in_state(self.state.clone())))
+        .add_systems(Update, keep_score.after(apply_damage).run_if(in_state(self.state.clone())));
+    }
+}
+
+fn tick_invulnerability(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut invulnerable: Query<(Entity, &mut Invulnerable)>,
+){
+    for (entity, mut invulnerable) in invulnerable.iter_mut() {
+        if invulnerable.0.tick(time.delta()).finished() {
+            commands.entity(entity).remove::<Invulnerable>();
+        }
+    }
+}
+
+fn apply_damage(
+    mut commands: Commands,
+    mut damages: EventReader<Damage>,
+    mut targets: Query<(&mut Health, &GlobalTransform, Option<&Invulnerable>, Option<&mut ExternalImpulse>)>,
+    mut damaged: EventWriter<Damaged>,
+    mut died: EventWriter<Died>,
+){
+    // Invulnerable is only inserted at the end of the frame, several hits in
+    // the same frame would all go through otherwise
+    let mut hit = HashSet::new();
+
+    for damage in damages.read() {
+        let Ok((mut health, transform, invulnerable, impulse)) = targets.get_mut(damage.target) else {
+            continue;
+        };
+        if health.is_dead() || invulnerable.is_some() || hit.contains(&damage.target) {
+            continue;
+        }
+
+        let amount = damage.amount.min(health.current);
+        health.current -= amount;
+        if let Some(mut impulse) = impulse {
+            impulse.impulse += damage.impulse;
+        }
+        damaged.send(Damaged { target: damage.target, amount, remaining: health.current, source: damage.source });
+
+        if health.is_dead() {
+            died.send(Died { entity: damage.target, source: damage.source, position: transform.translation() });
+        } else if health.invulnerability > 0. {
+            hit.insert(damage.target);
+            commands.entity(damage.target).insert(Invulnerable(Timer::from_seconds(health.invulnerability, TimerMode::Once)));
+        }
+    }
+}
+
+fn keep_score(
+    mut damaged: EventReader<Damaged>,
+    mut died: EventReader<Died>,
+    players: Query<Entity, With<Player>>,
+    mut score: ResMut<Score>,
+){
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    for damage in damaged.read() {
+        if damage.target == player {
+            info!("player hit for {:.0}, {:.0} left", damage.amount, damage.remaining);
+        } else if damage.source == Some(player) {
+            score.damage_dealt += damage.amount;
+        }
+    }
+    for death in died.read() {
+        if death.source == Some(player) {
+            score.kills += 1;
+            info!("{} kills, {:.0} damage dealt", score.kills, score.damage_dealt);
+        }
+    }
+}
+
+fn handle_deaths(
+    mut commands: Commands,
+    mut died: EventReader<Died>,
+    mortals: Query<&Mortal>,
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+    // world the drops are requested under
+    mut world_handle: Local<Option<usize>>,
+){
+    for death in died.read() {
+        let Ok(mortal) = mortals.get(death.entity) else {
+            continue;
+        };
+        if let Some(seconds) = mortal.despawn_after {
+            commands.entity(death.entity).insert(Despawning(Timer::from_seconds(seconds, TimerMode::Once)));
+        }
+        // drops missing from the manifest are loaded here, the scene shows up once it is in
+        if let Some(path) = &mortal.drop {
+            let world_handle = *world_handle.get_or_insert_with(|| assets.new_world());
+            let scene = assets.request::<Scene>(world_handle, path, &server);
+            commands.spawn(SceneBundle {
+                scene,
+                transform: Transform::from_translation(death.position),
+                ..default()
+            });
+        }
+    }
+}
+
+fn despawn_dead(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut dying: Query<(Entity, &mut Despawning)>,
+){
+    for (entity, mut despawning) in dying.iter_mut() {
+        if despawning.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
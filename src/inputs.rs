@@ -1,85 +1,61 @@
 
-use bevy::{input::mouse::MouseMotion, prelude::*, utils::info};
-use bevy_rapier3d::{control::KinematicCharacterController, dynamics::{ExternalImpulse, Velocity}};
+use bevy::{input::mouse::MouseMotion, prelude::*};
 
-use crate::{camera::{CameraRotationVelocity, MainCamera}, world::Player};
+use crate::{actions::{Action, Actions}, camera::{CameraRotationVelocity, MainCamera}, character::CharacterIntent, world::Player};
 
-pub struct InputsPlugin;
+pub struct InputsPlugin<S: States> {
+    pub state: S,
+}
 
 const ROTATION_SPEED: f32 = 0.125;
-const MOVE_SPEED: f32 = 0.7;
-const GRAVITY: Vec3 = Vec3::new(0., -16., 0.);
-const LIN_DAMPING: f32 = 7.;
 const ROT_DAMPING: f32 = 22.;
 
-#[derive(Default)]
-struct Key(pub Option<KeyCode>);
-
-impl Plugin for InputsPlugin {
+impl<S: States> Plugin for InputsPlugin<S> {
     fn build(&self, app: &mut App){
-        
+
         app
-        .add_event::<EffectEvent>()
-        .add_systems(Update, (catch_inputs, effect));
-        
+        .add_systems(Update, (toggle_camera, look_around, gather_intents).chain().run_if(in_state(self.state.clone())));
+
     }
 }
 
 
-fn catch_inputs (
-
-    mut camera_transform: Query<&mut Transform, With<MainCamera>>,
-
-    mut character_controller: Query<(
-        &mut KinematicCharacterController,
-        &mut Velocity,
-        &mut ExternalImpulse,
-        &mut CameraRotationVelocity
-    )>,
+fn toggle_camera(
     mut cameras: Query<&mut Camera>,
-    mut mouse_motion : EventReader<MouseMotion>,
-
-    keyboard: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
-    mut cooldown: Local<(f32, u32)>,
-    mut dash: Local<(f32, f32)>,
-    mut writer: EventWriter<EffectEvent>,
+    actions: Res<Actions>,
 ) {
-    dash.0 += time.delta_seconds();
-    cooldown.0 += time.delta_seconds();
-    if cooldown.0 > 5. {
-        cooldown.1 = 0;
-    }
-
-    if keyboard.pressed(KeyCode::Tab) {
-        if cooldown.0 > 1. {
-            
-            for mut camera in cameras.iter_mut(){
-                camera.is_active = !camera.is_active;
-            }
-            cooldown.0 = 0.;
-            cooldown.1 += 1;
+    if actions.just_pressed(Action::ToggleCamera) {
+        for mut camera in cameras.iter_mut(){
+            camera.is_active = !camera.is_active;
         }
     }
+}
 
+fn look_around (
 
+    mut camera_transform: Query<&mut Transform, With<MainCamera>>,
+    mut rotation: Query<&mut CameraRotationVelocity, With<Player>>,
+    mut mouse_motion : EventReader<MouseMotion>,
 
-
-
-    let (mut character_controller, mut velocity, mut external_force, mut rotation) =
-         character_controller.single_mut();
+    actions: Res<Actions>,
+    time: Res<Time>,
+) {
+    let (Ok(mut camera_transform), Ok(mut rotation)) = (camera_transform.get_single_mut(), rotation.get_single_mut()) else {
+        return;
+    };
 
     let mut motion_sum: Vec2 = Vec2::ZERO;
     for motion in mouse_motion.read() {
         motion_sum += motion.delta;
     }
+    // the right stick turns at a steady rate instead
+    motion_sum += actions.look * time.delta_seconds();
 
-    let mut camera_transform = camera_transform.single_mut();
     let angles = rotation.0;
     let mut yaw = angles.x;
     let mut pitch = angles.y;
 
-    if keyboard.pressed(KeyCode::KeyT) {
+    if actions.pressed(Action::Spin) {
 
         yaw = yaw * yaw + 100.;
         if yaw > 150. {
@@ -112,22 +88,24 @@ fn catch_inputs (
     let pitch_quaternion = Quat::from_axis_angle(right, -pitch * time.delta_seconds());
     //camera_transform.rotate_axis(right, -pitch * time.delta_seconds());
     camera_transform.rotate(pitch_quaternion);
-    
-    
+
+
     let yaw_quaternion = Quat::from_axis_angle(Vec3::Y, yaw *  time.delta_seconds());
     // camera_transform.rotate_y(yaw * time.delta_seconds());
     camera_transform.rotate(yaw_quaternion);
 
     rotation.0 = Vec3::new(yaw, pitch, 0.);
-    
-    if keyboard.pressed(KeyCode::Space) {
-        if cooldown.0 > 1. {
-            external_force.impulse += Vec3::Y * 8.;
-            //velocity.linvel.y += 11.;
-            cooldown.0 = 0.;
-            cooldown.1 += 1;
-        }
-    }
+}
+
+// movement is relative to where the camera looks, the character module does the rest
+pub fn gather_intents(
+    camera_transform: Query<&Transform, With<MainCamera>>,
+    mut intents: Query<&mut CharacterIntent, With<Player>>,
+    actions: Res<Actions>,
+) {
+    let (Ok(camera_transform), Ok(mut intent)) = (camera_transform.get_single(), intents.get_single_mut()) else {
+        return;
+    };
 
     // makes moving foward/left not affect the y dimension
     let mut forward:Vec3 = *camera_transform.forward();
@@ -135,76 +113,11 @@ fn catch_inputs (
     let mut left:Vec3 = *camera_transform.left();
     left.y = 0.;
 
-    let mut h_shift = Vec3::ZERO;
-
-    if keyboard.pressed(KeyCode::KeyW) {
-        h_shift += forward;
-    }
-    if keyboard.pressed(KeyCode::KeyS) {
-        h_shift -= forward;
-    }
-    if keyboard.pressed(KeyCode::KeyA) {
-        h_shift += left;
-    }
-    if keyboard.pressed(KeyCode::KeyD) {
-        h_shift -= left;
-    }
-
-
-    let horizontal_shift = h_shift.normalize_or_zero();
-
-    if keyboard.pressed(KeyCode::KeyR) {
-        if dash.0 > 2.5 {
-            writer.send(EffectEvent(horizontal_shift, 50.));
-            dash.0 = 0.
-        }
-    }
-
-    let gravity = GRAVITY * time.delta_seconds();
-    let mut damping = velocity.linvel;
-    damping.x *= -MOVE_SPEED * LIN_DAMPING;
-    damping.z *= -MOVE_SPEED * LIN_DAMPING;
-    damping *= time.delta_seconds();
-    velocity.linvel += horizontal_shift * MOVE_SPEED + damping + gravity;
-
-    character_controller.translation = Some(velocity.linvel * time.delta_seconds());
+    // keeps the stick's tilt, a half pushed stick walks at half speed
+    let h_shift = forward.normalize_or_zero() * actions.movement.y - left.normalize_or_zero() * actions.movement.x;
 
+    intent.movement = h_shift.clamp_length_max(1.);
+    intent.jump = actions.just_pressed(Action::Jump);
+    intent.jump_held = actions.pressed(Action::Jump);
+    intent.dash = actions.pressed(Action::Dash);
 }
-
-
-#[derive(Event, Clone)]
-struct EffectEvent(pub Vec3, pub f32);
-
-fn effect(
-
-    time: Res<Time>,
-
-
-    mut event_set: ParamSet<(
-        EventReader<EffectEvent>,
-        EventWriter<EffectEvent>,
-    )>,
-
-    mut character_velocity: Query<&mut Velocity, With<Player>>,
-) {
-
-    let mut events: Vec<EffectEvent> = Vec::new();
-
-    for event in event_set.p0().read() {
-        events.push(event.clone());
-    }
-
-    for mut event in events {
-
-        let dash = 200. * time.delta_seconds();
-        event.1 -= dash;
-        let advance = event.0 * dash;
-        character_velocity.single_mut().linvel += advance;
-
-        if event.1 > 0. {
-            event_set.p1().send(event);
-        }
-
-    }
-
-}
\ No newline at end of file
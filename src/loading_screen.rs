@@ -0,0 +1,204 @@
+use bevy::prelude::*;
+
+use crate::asset_loader::{FailedAssets, GameAssets};
+
+const FADE_SECONDS: f32 = 0.6;
+const BACKGROUND: Color = Color::rgb(0.06, 0.02, 0.02);
+const BAR_COLOR: Color = Color::rgb(0.75, 0.12, 0.05);
+
+#[derive(Component)]
+struct LoadingScreen;
+
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct ProgressText;
+
+#[derive(Component)]
+struct FailedText;
+
+#[derive(Component)]
+struct FadeOut(Timer);
+
+// What failed to load, stays over the game once the loading screen is gone.
+#[derive(Component)]
+struct FailurePanel;
+
+pub struct LoadingScreenPlugin<S: States> {
+    pub state: S,
+}
+impl<S: States> Plugin for LoadingScreenPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_systems(OnEnter(self.state.clone()), spawn_loading_screen)
+        .add_systems(Update, (update_loading_screen, update_failed_text).run_if(in_state(self.state.clone())))
+        .add_systems(OnExit(self.state.clone()), (start_fade_out, show_failures))
+        .add_systems(Update, (fade_out, dismiss_failures));
+    }
+}
+
+fn spawn_loading_screen(
+    mut commands: Commands,
+){
+    commands.spawn((LoadingScreen, NodeBundle{
+        style: Style{
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            row_gap: Val::Px(16.),
+            ..default()
+        },
+        background_color: BackgroundColor(BACKGROUND),
+        z_index: ZIndex::Global(100),
+        ..default()
+    })).with_children(|screen| {
+
+        screen.spawn((ProgressText, TextBundle::from_section("Loading 0%", TextStyle{color: Color::WHITE, font_size: 32.0, ..default()})));
+
+        screen.spawn(NodeBundle{
+            style: Style{
+                width: Val::Percent(40.),
+                height: Val::Px(12.),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::rgb(0.2, 0.2, 0.2)),
+            ..default()
+        }).with_children(|bar| {
+            bar.spawn((ProgressBar, NodeBundle{
+                style: Style{
+                    width: Val::Percent(0.),
+                    height: Val::Percent(100.),
+                    ..default()
+                },
+                background_color: BackgroundColor(BAR_COLOR),
+                ..default()
+            }));
+        });
+
+        screen.spawn((FailedText, TextBundle::from_section("", TextStyle{color: Color::ORANGE_RED, font_size: 16.0, ..default()})));
+    });
+}
+
+fn update_loading_screen(
+    assets: Res<GameAssets>,
+    mut bar: Query<&mut Style, With<ProgressBar>>,
+    mut texts: Query<&mut Text, With<ProgressText>>,
+){
+    if assets.loading_worlds().is_empty() {
+        return;
+    }
+
+    let progress = assets.loading_progress();
+    for mut style in bar.iter_mut() {
+        style.width = Val::Percent(progress * 100.);
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!("Loading {:.0}%", progress * 100.);
+    }
+}
+
+fn update_failed_text(
+    assets: Res<GameAssets>,
+    mut texts: Query<&mut Text, With<FailedText>>,
+){
+    let failed = assets.loading_failures();
+    if failed.is_empty() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!("Failed to load:\n{}", failed.join("\n"));
+    }
+}
+
+fn start_fade_out(
+    mut commands: Commands,
+    screens: Query<Entity, With<LoadingScreen>>,
+    mut bar: Query<&mut Style, With<ProgressBar>>,
+    mut texts: Query<&mut Text, With<ProgressText>>,
+){
+    for mut style in bar.iter_mut() {
+        style.width = Val::Percent(100.);
+    }
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = "Loading 100%".to_string();
+    }
+    for screen in screens.iter() {
+        commands.entity(screen).insert(FadeOut(Timer::from_seconds(FADE_SECONDS, TimerMode::Once)));
+    }
+}
+
+fn fade_out(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut screens: Query<(Entity, &mut FadeOut)>,
+    mut backgrounds: Query<&mut BackgroundColor>,
+    mut texts: Query<&mut Text>,
+    children: Query<&Children>,
+){
+    for (screen, mut fade) in screens.iter_mut() {
+        fade.0.tick(time.delta());
+        let alpha = fade.0.fraction_remaining();
+
+        for node in std::iter::once(screen).chain(children.iter_descendants(screen)) {
+            if let Ok(mut background) = backgrounds.get_mut(node) {
+                background.0.set_a(alpha);
+            }
+            if let Ok(mut text) = texts.get_mut(node) {
+                for section in text.sections.iter_mut() {
+                    section.style.color.set_a(alpha);
+                }
+            }
+        }
+
+        if fade.0.finished() {
+            commands.entity(screen).despawn_recursive();
+        }
+    }
+}
+
+fn show_failures(
+    mut commands: Commands,
+    failed: Res<FailedAssets>,
+    panels: Query<(), With<FailurePanel>>,
+){
+    if failed.0.is_empty() || !panels.is_empty() {
+        return;
+    }
+    let text = format!("Failed to load:\n{}\n\nPress Enter to dismiss", failed.0.join("\n"));
+    commands.spawn((FailurePanel, TextBundle::from_section(text, TextStyle{color: Color::ORANGE_RED, font_size: 16.0, ..default()})
+        .with_style(Style{
+            position_type: PositionType::Absolute,
+            top: Val::Px(16.),
+            left: Val::Px(16.),
+            padding: UiRect::all(Val::Px(8.)),
+            ..default()
+        })
+        .with_background_color(BACKGROUND.with_a(0.8)),
+    )).insert(ZIndex::Global(101));
+}
+
+fn dismiss_failures(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut failed: ResMut<FailedAssets>,
+    panels: Query<Entity, With<FailurePanel>>,
+){
+    if panels.is_empty() {
+        return;
+    }
+    let dismissed = keyboard.just_pressed(KeyCode::Enter)
+        || gamepad_buttons.get_just_pressed().any(|button| button.button_type == GamepadButtonType::Start);
+    if !dismissed {
+        return;
+    }
+    for panel in panels.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    failed.0.clear();
+}
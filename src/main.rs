@@ -1,20 +1,56 @@
 use bevy::{app::PluginGroupBuilder, asset::AssetMetaCheck, input::keyboard::KeyboardInput, prelude::*, window::{Cursor, WindowFocused, WindowResolution}};
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
 
+use actions::ActionsPlugin;
+use animation::AnimationPlugin;
 use asset_loader::AssetLoaderPlugin;
+use body::PlayerBodyPlugin;
 use bevy_rapier3d::{plugin::{NoUserData, RapierPhysicsPlugin}, render::RapierDebugRenderPlugin};
 
 use camera::MainCameraPlugin;
+use character::CharacterPlugin;
+use decoration::DecorationPlugin;
 use inputs::InputsPlugin;
+use loading_screen::LoadingScreenPlugin;
+use manifest::{CAVE_BUNDLE, CORE_BUNDLE, MOBS_BUNDLE, PLAYER_BUNDLE, WEAPONS_BUNDLE};
+use health::HealthPlugin;
+use melee::MeleePlugin;
+use weapon::WeaponPlugin;
+use mob_state::MobStatePlugin;
+use navigation::NavigationPlugin;
+use flow_field::FlowFieldPlugin;
+use stress::StressTestPlugin;
+use spawner::SpawnerPlugin;
 use states::GameState;
 use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
 use world::WorldPlugin;
+use world_config::WorldConfigPlugin;
 
 mod inputs;
+mod actions;
+mod character;
+mod animation;
+mod body;
 mod flat_mesh;
+mod decoration;
+mod noise;
+mod seed;
+mod spawner;
+mod health;
+mod melee;
+mod weapon;
+mod mob_state;
+mod navigation;
+mod flow_field;
+mod stress;
+mod steering;
 mod asset_loader;
+mod loading_screen;
+mod manifest;
+mod ron_loader;
 mod camera;
 mod world;
+mod world_config;
 mod states;
 mod utils;
 
@@ -60,9 +96,9 @@ fn main() {
         }),
         ..default()
     }))
-    .insert_state(GameState::InGame)
+    .insert_state(GameState::Loading)
     .add_plugins(UtilsPluginGroup)
-    .add_plugins(LoadingGamePluginGroup {state: GameState::InGame})
+    .add_plugins(LoadingGamePluginGroup {state: GameState::Loading, next_state: GameState::InGame})
     .add_plugins(GamePluginGroup {state: GameState::InGame})
     .add_systems(Startup, test)
     .add_systems(Update, button_interaction)
@@ -149,11 +185,17 @@ impl PluginGroup for UtilsPluginGroup {
 
 struct LoadingGamePluginGroup<S: States> {
     pub state: S,
+    pub next_state: S,
 }
 impl<S: States> PluginGroup for LoadingGamePluginGroup<S> {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
-        .add(AssetLoaderPlugin{state: self.state})
+        .add(AssetLoaderPlugin{
+            state: self.state.clone(),
+            next_state: self.next_state,
+            preload: vec![(self.state.clone(), vec![CORE_BUNDLE, CAVE_BUNDLE, WEAPONS_BUNDLE, MOBS_BUNDLE, PLAYER_BUNDLE])],
+        })
+        .add(LoadingScreenPlugin{state: self.state})
     }
 }
 
@@ -163,9 +205,22 @@ struct GamePluginGroup<S: States> {
 impl<S: States> PluginGroup for GamePluginGroup<S> {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
-        .add(WorldPlugin {state: self.state})
-        .add(InputsPlugin)
-        //.add(SpawnerPlugin)
+        .add(DecorationPlugin)
+        .add(WorldConfigPlugin)
+        .add(WorldPlugin {state: self.state.clone()})
+        .add(ActionsPlugin)
+        .add(InputsPlugin {state: self.state.clone()})
+        .add(CharacterPlugin {state: self.state.clone()})
+        .add(HealthPlugin {state: self.state.clone()})
+        .add(WeaponPlugin {state: self.state.clone()})
+        .add(MeleePlugin {state: self.state.clone()})
+        .add(SpawnerPlugin {state: self.state.clone()})
+        .add(MobStatePlugin {state: self.state.clone()})
+        .add(NavigationPlugin {state: self.state.clone()})
+        .add(FlowFieldPlugin {state: self.state.clone()})
+        .add(PlayerBodyPlugin {state: self.state.clone()})
+        .add(StressTestPlugin {state: self.state})
+        .add(AnimationPlugin)
         .add(MainCameraPlugin)
     }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{asset_loader::GameAssets, decoration::DecorationCatalogue, weapon::WeaponDefinition, world_config::WorldGenConfig};
+
+pub const MANIFEST_PATH: &str = "manifest.assets.ron";
+
+pub const CORE_BUNDLE: &str = "core";
+pub const CAVE_BUNDLE: &str = "cave_biome";
+pub const WEAPONS_BUNDLE: &str = "weapons";
+pub const MOBS_BUNDLE: &str = "mobs";
+pub const PLAYER_BUNDLE: &str = "player";
+
+#[derive(Asset, TypePath, Deserialize)]
+pub struct AssetManifest {
+    pub bundles: HashMap<String, Vec<ManifestEntry>>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum ManifestEntry {
+    Image(String),
+    Scene(String),
+    Animation(String),
+    Decorations(String),
+    WorldGen(String),
+    Weapon(String),
+}
+
+impl ManifestEntry {
+
+    pub fn path(&self) -> &str {
+        match self {
+            ManifestEntry::Image(path)
+            | ManifestEntry::Scene(path)
+            | ManifestEntry::Animation(path)
+            | ManifestEntry::Decorations(path)
+            | ManifestEntry::WorldGen(path)
+            | ManifestEntry::Weapon(path) => path,
+        }
+    }
+
+    pub fn request(&self, assets: &mut GameAssets, world_handle: usize, server: &AssetServer) {
+        match self {
+            ManifestEntry::Image(path) => { assets.request::<Image>(world_handle, path, server); },
+            ManifestEntry::Scene(path) => { assets.request::<Scene>(world_handle, path, server); },
+            ManifestEntry::Animation(path) => { assets.request::<AnimationClip>(world_handle, path, server); },
+            ManifestEntry::Decorations(path) => { assets.request::<DecorationCatalogue>(world_handle, path, server); },
+            ManifestEntry::WorldGen(path) => { assets.request::<WorldGenConfig>(world_handle, path, server); },
+            ManifestEntry::Weapon(path) => { assets.request::<WeaponDefinition>(world_handle, path, server); },
+        }
+    }
+}
+
+impl AssetManifest {
+
+    // logs every entry whose file is missing from the assets folder, and every
+    // bundle the game asks for that the manifest does not define
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn validate(&self, expected_bundles: &[&str]) -> bool {
+        let root = bevy::asset::io::file::FileAssetReader::get_base_path().join("assets");
+        let mut valid = true;
+
+        for bundle in expected_bundles {
+            if !self.bundles.contains_key(*bundle) {
+                error!("asset manifest has no bundle named {}", bundle);
+                valid = false;
+            }
+        }
+
+        for (bundle, entries) in self.bundles.iter() {
+            for entry in entries {
+                // labels (`#Scene0`) point inside the file
+                let file = entry.path().split('#').next().unwrap_or_default();
+                if !root.join(file).is_file() {
+                    error!("asset manifest bundle {}: missing file {}", bundle, file);
+                    valid = false;
+                }
+            }
+        }
+        valid
+    }
+
+    // files cannot be listed from the browser, failed loads get reported instead
+    #[cfg(target_arch = "wasm32")]
+    pub fn validate(&self, expected_bundles: &[&str]) -> bool {
+        let mut valid = true;
+        for bundle in expected_bundles {
+            if !self.bundles.contains_key(*bundle) {
+                error!("asset manifest has no bundle named {}", bundle);
+                valid = false;
+            }
+        }
+        valid
+    }
+}
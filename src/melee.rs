@@ -0,0 +1,141 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_rapier3d::{geometry::ColliderDisabled, plugin::RapierContext};
+
+use crate::{actions::{Action, Actions}, animation::{AnimState, AnimationController}, health::Damage, spawner::Mob, weapon::{Weapon, WeaponDefinition, WeaponHitbox}, world::Player};
+
+#[derive(Component, Default)]
+pub struct Melee {
+    // seconds into the current swing
+    swing: Option<f32>,
+    since_swing: f32,
+    combo: u32,
+    queued: bool,
+    hit: HashSet<Entity>,
+}
+
+impl Melee {
+
+    pub fn ready(weapon: &WeaponDefinition) -> Self {
+        Melee { since_swing: weapon.cooldown, ..default() }
+    }
+}
+
+pub struct MeleePlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for MeleePlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_systems(Update, (swing_weapon, weapon_hits).chain().run_if(in_state(self.state.clone())));
+    }
+}
+
+fn swing_weapon(
+    mut commands: Commands,
+    time: Res<Time>,
+    actions: Res<Actions>,
+    mut players: Query<&mut Melee, With<Player>>,
+    hitboxes: Query<(Entity, Has<ColliderDisabled>), With<WeaponHitbox>>,
+    mut weapons: Query<(&Weapon, &mut AnimationController)>,
+    definitions: Res<Assets<WeaponDefinition>>,
+){
+    let (Ok(mut melee), Ok((weapon, mut animation))) = (players.get_single_mut(), weapons.get_single_mut()) else {
+        return;
+    };
+    let Some(settings) = definitions.get(&weapon.definition) else {
+        return;
+    };
+    let dt = time.delta_seconds();
+
+    melee.since_swing += dt;
+    let clicked = actions.just_pressed(Action::Attack);
+
+    let mut start = false;
+    match melee.swing {
+        Some(elapsed) => {
+            let elapsed = elapsed + dt;
+            // a click late in the swing is kept for the next combo step
+            if clicked && elapsed > settings.windup + settings.active {
+                melee.queued = true;
+            }
+            if elapsed >= settings.swing {
+                melee.swing = None;
+                melee.since_swing = 0.;
+                if melee.queued && melee.combo < settings.combo_steps {
+                    start = true;
+                }
+                melee.queued = false;
+            } else {
+                melee.swing = Some(elapsed);
+            }
+        }
+        None if clicked => {
+            if melee.combo > 0 && melee.combo < settings.combo_steps && melee.since_swing <= settings.combo_window {
+                start = true;
+            } else if melee.since_swing >= settings.cooldown {
+                melee.combo = 0;
+                start = true;
+            }
+        }
+        None => (),
+    }
+
+    if start {
+        melee.combo += 1;
+        melee.swing = Some(0.);
+        melee.hit.clear();
+        // the controller goes back to idle once the swing clip is over
+        animation.trigger(AnimState::Attack);
+    }
+    if melee.swing.is_none() && melee.since_swing > settings.combo_window {
+        melee.combo = 0;
+    }
+
+    let active = melee.swing.is_some_and(|elapsed| elapsed >= settings.windup && elapsed < settings.windup + settings.active);
+    for (hitbox, disabled) in hitboxes.iter() {
+        if active && disabled {
+            commands.entity(hitbox).remove::<ColliderDisabled>();
+        } else if !active && !disabled {
+            commands.entity(hitbox).insert(ColliderDisabled);
+        }
+    }
+}
+
+// every mob in the sensor takes one hit per swing
+fn weapon_hits(
+    rapier: Res<RapierContext>,
+    mut players: Query<(Entity, &mut Melee, &GlobalTransform), With<Player>>,
+    hitboxes: Query<Entity, (With<WeaponHitbox>, Without<ColliderDisabled>)>,
+    weapons: Query<&Weapon>,
+    mobs: Query<&GlobalTransform, With<Mob>>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut damages: EventWriter<Damage>,
+){
+    let (Ok((player, mut melee, player_transform)), Ok(hitbox), Ok(weapon)) = (players.get_single_mut(), hitboxes.get_single(), weapons.get_single()) else {
+        return;
+    };
+    let Some(settings) = definitions.get(&weapon.definition) else {
+        return;
+    };
+    let damage = settings.damage * (1. + settings.combo_bonus * melee.combo.saturating_sub(1) as f32);
+
+    for (a, b, intersecting) in rapier.intersection_pairs_with(hitbox) {
+        let other = if a == hitbox { b } else { a };
+        let Ok(mob_transform) = mobs.get(other) else {
+            continue;
+        };
+        if !intersecting || !melee.hit.insert(other) {
+            continue;
+        }
+        let mut away = mob_transform.translation() - player_transform.translation();
+        away.y = 0.;
+        damages.send(Damage {
+            target: other,
+            amount: damage,
+            source: Some(player),
+            impulse: (away.normalize_or_zero() + Vec3::Y * 0.3) * settings.knockback,
+        });
+    }
+}
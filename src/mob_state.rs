@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+use bevy_rapier3d::{dynamics::{Sleeping, Velocity}, pipeline::QueryFilter, plugin::RapierContext};
+
+use crate::{animation::{AnimState, AnimationController}, health::{Damage, Damaged, Health}, spawner::Mob, steering::steer_mobs, world::Player};
+
+const EYE_HEIGHT: f32 = 0.5;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MobState {
+    Idle,
+    Chase,
+    Attack,
+    Flee,
+    Dead,
+}
+
+// Sent when an attack lands at the end of its wind up.
+#[derive(Event)]
+pub struct MobStrike {
+    pub mob: Entity,
+    pub target: Entity,
+}
+
+// Per mob type tuning of the state machine.
+#[derive(Clone, Debug)]
+pub struct Behaviour {
+    pub sight_radius: f32,
+    // the chase is only given up past this radius, or once the player is hidden
+    pub lose_radius: f32,
+    pub attack_range: f32,
+    // seconds the attack is telegraphed before it lands, then the recovery
+    pub attack_windup: f32,
+    pub attack_recovery: f32,
+    // below this health fraction a hit makes the mob run away for a while
+    pub flee_below: f32,
+    pub flee_duration: f32,
+    pub attack_damage: f32,
+    // pushes the target away from the mob when an attack lands
+    pub attack_knockback: f32,
+}
+
+impl Behaviour {
+
+    pub fn ling() -> Self {
+        Behaviour {
+            sight_radius: 40.,
+            lose_radius: 55.,
+            attack_range: 1.8,
+            attack_windup: 0.5,
+            attack_recovery: 0.6,
+            flee_below: 0.3,
+            flee_duration: 3.,
+            attack_damage: 10.,
+            attack_knockback: 2.,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MobBrain {
+    pub behaviour: Behaviour,
+    // seconds spent in the current state
+    pub elapsed: f32,
+    struck: bool,
+    last_health: Option<f32>,
+}
+
+impl MobBrain {
+
+    pub fn new(behaviour: Behaviour) -> Self {
+        MobBrain { behaviour, elapsed: 0., struck: false, last_health: None }
+    }
+}
+
+pub struct MobStatePlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for MobStatePlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .add_event::<MobStrike>()
+        .add_systems(Update, (update_mob_states.before(steer_mobs), strike).chain().run_if(in_state(self.state.clone())))
+        .add_systems(Update, animate_mobs);
+    }
+}
+
+fn line_of_sight(rapier: &RapierContext, from: Vec3, to: Vec3) -> bool {
+    let ray = to - from;
+    let distance = ray.length();
+    if distance <= f32::EPSILON {
+        return true;
+    }
+    rapier.cast_ray(from, ray / distance, distance, true, QueryFilter::only_fixed()).is_none()
+}
+
+type PlayerOnly = (With<Player>, Without<Mob>);
+type MobStateItem = (Entity, &'static Transform, &'static mut MobState, &'static mut MobBrain, &'static Sleeping, &'static mut Velocity, Option<&'static Health>);
+
+fn update_mob_states(
+    time: Res<Time>,
+    rapier: Res<RapierContext>,
+    player: Query<(Entity, &Transform), PlayerOnly>,
+    mut mobs: Query<MobStateItem, With<Mob>>,
+    mut strikes: EventWriter<MobStrike>,
+){
+    let Ok((player, player_transform)) = player.get_single() else {
+        return;
+    };
+    let dt = time.delta_seconds();
+
+    for (entity, transform, mut state, mut brain, sleeping, mut velocity, health) in mobs.iter_mut() {
+        brain.elapsed += dt;
+
+        // the health plugin despawns it
+        if *state == MobState::Dead {
+            velocity.linvel.x = 0.;
+            velocity.linvel.z = 0.;
+            continue;
+        }
+        if sleeping.sleeping {
+            continue;
+        }
+
+        let was_hit = match (health, brain.last_health) {
+            (Some(health), Some(last)) => health.current < last,
+            _ => false,
+        };
+        brain.last_health = health.map(|health| health.current);
+
+        let to_player = player_transform.translation - transform.translation;
+        let distance = Vec3::new(to_player.x, 0., to_player.z).length();
+        let behaviour = &brain.behaviour;
+        // only cast when the answer matters
+        let sees = distance < behaviour.lose_radius
+            && line_of_sight(&rapier, transform.translation + Vec3::Y * EYE_HEIGHT, player_transform.translation);
+
+        let next = if health.is_some_and(|health| health.is_dead()) {
+            MobState::Dead
+        } else if was_hit && health.is_some_and(|health| health.fraction() < behaviour.flee_below) {
+            MobState::Flee
+        } else {
+            match *state {
+                MobState::Idle if sees && distance < behaviour.sight_radius => MobState::Chase,
+                MobState::Chase if !sees => MobState::Idle,
+                MobState::Chase if distance <= behaviour.attack_range => MobState::Attack,
+                // a hit interrupts the wind up
+                MobState::Attack if was_hit => MobState::Chase,
+                MobState::Attack if brain.elapsed > behaviour.attack_windup + behaviour.attack_recovery => MobState::Chase,
+                MobState::Flee if brain.elapsed > behaviour.flee_duration => if sees { MobState::Chase } else { MobState::Idle },
+                current => current,
+            }
+        };
+
+        if *state == MobState::Attack && next == MobState::Attack
+            && !brain.struck && brain.elapsed >= brain.behaviour.attack_windup {
+            brain.struck = true;
+            if distance <= brain.behaviour.attack_range * 1.25 {
+                strikes.send(MobStrike { mob: entity, target: player });
+            }
+        }
+
+        if next != *state {
+            *state = next;
+            brain.elapsed = 0.;
+            brain.struck = false;
+        }
+    }
+}
+
+fn strike(
+    mut strikes: EventReader<MobStrike>,
+    mut damages: EventWriter<Damage>,
+    mobs: Query<(&GlobalTransform, &MobBrain)>,
+    targets: Query<&GlobalTransform>,
+){
+    for strike in strikes.read() {
+        let (Ok((mob, brain)), Ok(target)) = (mobs.get(strike.mob), targets.get(strike.target)) else {
+            continue;
+        };
+        let mut away = target.translation() - mob.translation();
+        away.y = 0.;
+        let away = away.normalize_or_zero();
+        damages.send(Damage {
+            target: strike.target,
+            amount: brain.behaviour.attack_damage,
+            source: Some(strike.mob),
+            impulse: (away + Vec3::Y * 0.5) * brain.behaviour.attack_knockback,
+        });
+    }
+}
+
+fn animate_mobs(
+    mut mobs: Query<(Ref<MobState>, &mut AnimationController), With<Mob>>,
+    mut damaged: EventReader<Damaged>,
+){
+    for (state, mut controller) in mobs.iter_mut() {
+        if !state.is_changed() {
+            continue;
+        }
+        controller.set_state(match *state {
+            MobState::Idle => AnimState::Walk,
+            MobState::Chase | MobState::Flee => AnimState::Run,
+            MobState::Attack => AnimState::Attack,
+            MobState::Dead => AnimState::Die,
+        });
+    }
+    for damage in damaged.read().filter(|damage| damage.remaining > 0.) {
+        if let Ok((_, mut controller)) = mobs.get_mut(damage.target) {
+            controller.trigger(AnimState::Hit);
+        }
+    }
+}
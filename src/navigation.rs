@@ -0,0 +1,467 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::{geometry::Collider, pipeline::QueryFilter, plugin::RapierContext};
+
+use crate::{flow_field::FlowField, mob_state::MobState, spawner::Mob, steering::steer_mobs, world::{Chunk, Decorated, Player}, world_config::WorldGenConfig};
+
+// rays start this high above the ground looking for it
+const RAY_TOP: f32 = 500.;
+
+#[derive(Resource)]
+pub struct NavSettings {
+    pub cell_size: f32,
+    // the agent every cell is tested for, sized for the biggest mob
+    pub agent_radius: f32,
+    pub agent_height: f32,
+    pub max_step: f32,
+    // degrees
+    pub max_slope: f32,
+    // baking and searching are spread over frames
+    pub bakes_per_frame: usize,
+    pub paths_per_frame: usize,
+    pub max_search_nodes: usize,
+    // seconds before a path is planned again even if the player did not move
+    pub repath_interval: f32,
+}
+
+impl Default for NavSettings {
+    fn default() -> Self {
+        NavSettings {
+            cell_size: 1.,
+            agent_radius: 0.5,
+            agent_height: 1.,
+            max_step: 0.5,
+            max_slope: 45.,
+            bakes_per_frame: 1,
+            paths_per_frame: 16,
+            max_search_nodes: 4000,
+            repath_interval: 1.,
+        }
+    }
+}
+
+struct ChunkNav {
+    walkable: Vec<bool>,
+    height: Vec<f32>,
+}
+
+// Walkability of every loaded chunk, in cells of NavSettings::cell_size.
+// Cells of chunks that are not baked yet count as blocked.
+#[derive(Resource, Default)]
+pub struct NavGrid {
+    cell_size: f32,
+    chunk_cells: i32,
+    chunks: HashMap<(i32, i32), ChunkNav>,
+    baked: HashMap<Entity, (i32, i32)>,
+    pending: Vec<(Entity, (i32, i32))>,
+    // bumped whenever a chunk is baked or dropped
+    version: u32,
+}
+
+impl NavGrid {
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn cell_of(&self, position: Vec3) -> IVec2 {
+        IVec2::new((position.x / self.cell_size).floor() as i32, (position.z / self.cell_size).floor() as i32)
+    }
+
+    fn lookup(&self, cell: IVec2) -> Option<(&ChunkNav, usize)> {
+        if self.chunk_cells == 0 {
+            return None;
+        }
+        let chunk = (cell.x.div_euclid(self.chunk_cells), cell.y.div_euclid(self.chunk_cells));
+        let local = (cell.x.rem_euclid(self.chunk_cells), cell.y.rem_euclid(self.chunk_cells));
+        self.chunks.get(&chunk).map(|nav| (nav, (local.1 * self.chunk_cells + local.0) as usize))
+    }
+
+    pub fn is_walkable(&self, cell: IVec2) -> bool {
+        self.lookup(cell).is_some_and(|(nav, index)| nav.walkable[index])
+    }
+
+    pub fn height(&self, cell: IVec2) -> Option<f32> {
+        self.lookup(cell).map(|(nav, index)| nav.height[index])
+    }
+
+    pub fn cell_center(&self, cell: IVec2) -> Vec3 {
+        Vec3::new(
+            (cell.x as f32 + 0.5) * self.cell_size,
+            self.height(cell).unwrap_or_default(),
+            (cell.y as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    // walkable neighbours with their move cost, diagonals may not cut corners
+    pub fn neighbours(&self, cell: IVec2, max_step: f32) -> impl Iterator<Item = (IVec2, f32)> + '_ {
+        let height = self.height(cell);
+        [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)]
+        .into_iter()
+        .filter_map(move |(dx, dz)| {
+            let next = cell + IVec2::new(dx, dz);
+            if !self.is_walkable(next) {
+                return None;
+            }
+            if dx != 0 && dz != 0 && !(self.is_walkable(cell + IVec2::new(dx, 0)) && self.is_walkable(cell + IVec2::new(0, dz))) {
+                return None;
+            }
+            let climb = (self.height(next)? - height?).abs();
+            if climb > max_step {
+                return None;
+            }
+            Some((next, if dx != 0 && dz != 0 { std::f32::consts::SQRT_2 } else { 1. }))
+        })
+    }
+
+    // closest walkable cell within a few rings, for goals standing against a wall
+    pub fn nearest_walkable(&self, cell: IVec2, rings: i32) -> Option<IVec2> {
+        (0..=rings).find_map(|ring| {
+            (-ring..=ring)
+            .flat_map(|dx| (-ring..=ring).map(move |dz| IVec2::new(dx, dz)))
+            .filter(|offset| offset.x.abs() == ring || offset.y.abs() == ring)
+            .map(|offset| cell + offset)
+            .find(|&candidate| self.is_walkable(candidate))
+        })
+    }
+
+    // A* over the loaded chunks, gives up after max_nodes expansions and
+    // returns the way to the closest cell reached instead
+    pub fn find_path(&self, from: Vec3, to: Vec3, max_step: f32, max_nodes: usize) -> Option<Vec<Vec3>> {
+        let start = self.nearest_walkable(self.cell_of(from), 2)?;
+        let goal = self.nearest_walkable(self.cell_of(to), 2)?;
+
+        let estimate = |cell: IVec2| {
+            let d = (goal - cell).abs();
+            let (min, max) = (d.x.min(d.y) as f32, d.x.max(d.y) as f32);
+            max - min + min * std::f32::consts::SQRT_2
+        };
+
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<IVec2, IVec2> = HashMap::default();
+        let mut cost: HashMap<IVec2, f32> = HashMap::default();
+        let mut closest = (estimate(start), start);
+
+        cost.insert(start, 0.);
+        open.push(OpenCell { score: estimate(start), cell: start });
+
+        let mut expanded = 0;
+        while let Some(OpenCell { cell, .. }) = open.pop() {
+            if cell == goal {
+                closest = (0., goal);
+                break;
+            }
+            expanded += 1;
+            if expanded > max_nodes {
+                break;
+            }
+            let current = cost[&cell];
+            for (next, step) in self.neighbours(cell, max_step) {
+                let next_cost = current + step;
+                if cost.get(&next).is_some_and(|&known| known <= next_cost) {
+                    continue;
+                }
+                cost.insert(next, next_cost);
+                came_from.insert(next, cell);
+                let remaining = estimate(next);
+                if remaining < closest.0 {
+                    closest = (remaining, next);
+                }
+                open.push(OpenCell { score: next_cost + remaining, cell: next });
+            }
+        }
+
+        let mut cells = vec![closest.1];
+        while let Some(&previous) = came_from.get(cells.last()?) {
+            cells.push(previous);
+        }
+        cells.reverse();
+
+        // only keep the corners
+        let mut path = Vec::new();
+        for (index, &cell) in cells.iter().enumerate().skip(1) {
+            let straight = cells.get(index + 1).is_some_and(|&next| next - cell == cell - cells[index - 1]);
+            if !straight {
+                path.push(self.cell_center(cell));
+            }
+        }
+        Some(path)
+    }
+}
+
+// flat ground at height 0 over the given chunks, without rapier
+#[cfg(test)]
+impl NavGrid {
+
+    pub(crate) fn flat(cell_size: f32, chunk_cells: i32, chunks: impl IntoIterator<Item = (i32, i32)>, blocked: &[IVec2]) -> Self {
+        let cells = (chunk_cells * chunk_cells) as usize;
+        let mut grid = NavGrid { cell_size, chunk_cells, ..default() };
+        for coords in chunks {
+            grid.chunks.insert(coords, ChunkNav { walkable: vec![true; cells], height: vec![0.; cells] });
+        }
+        for &cell in blocked {
+            let chunk = (cell.x.div_euclid(chunk_cells), cell.y.div_euclid(chunk_cells));
+            let local = (cell.x.rem_euclid(chunk_cells), cell.y.rem_euclid(chunk_cells));
+            if let Some(nav) = grid.chunks.get_mut(&chunk) {
+                nav.walkable[(local.1 * chunk_cells + local.0) as usize] = false;
+            }
+        }
+        grid
+    }
+}
+
+#[derive(PartialEq)]
+pub(crate) struct OpenCell {
+    pub score: f32,
+    pub cell: IVec2,
+}
+
+impl Eq for OpenCell {}
+
+// lowest score first out of the max-heap
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.score.total_cmp(&self.score)
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Waypoints toward the player, planned by the NavGrid while chasing.
+#[derive(Component, Default)]
+pub struct NavPath {
+    pub waypoints: Vec<Vec3>,
+    goal: Option<IVec2>,
+    age: f32,
+}
+
+impl NavPath {
+
+    // next waypoint, dropping the ones already reached
+    pub fn next(&mut self, position: Vec3, reach: f32) -> Option<Vec3> {
+        while let Some(&waypoint) = self.waypoints.first() {
+            if Vec2::new(waypoint.x - position.x, waypoint.z - position.z).length() > reach {
+                return Some(waypoint);
+            }
+            self.waypoints.remove(0);
+        }
+        None
+    }
+}
+
+pub struct NavigationPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for NavigationPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_resource::<NavSettings>()
+        .init_resource::<NavGrid>()
+        .add_systems(Update, (track_chunks, bake_chunks).chain())
+        .add_systems(Update, plan_paths.before(steer_mobs).run_if(in_state(self.state.clone())));
+    }
+}
+
+// The props come after the ground, a decorated chunk is baked again with their
+// colliders and keeps its previous cells until then.
+fn track_chunks(
+    mut grid: ResMut<NavGrid>,
+    added: Query<(Entity, &Chunk), Added<Chunk>>,
+    decorated: Query<(Entity, &Chunk), Added<Decorated>>,
+    mut removed: RemovedComponents<Chunk>,
+){
+    for entity in removed.read() {
+        if let Some(coords) = grid.baked.remove(&entity) {
+            grid.chunks.remove(&coords);
+            grid.version += 1;
+        }
+        grid.pending.retain(|(pending, _)| *pending != entity);
+    }
+    for (entity, chunk) in added.iter().chain(decorated.iter()) {
+        if !grid.pending.iter().any(|(pending, _)| *pending == entity) {
+            grid.pending.push((entity, (chunk.0, chunk.1)));
+        }
+    }
+}
+
+// Chunks are baked once rapier knows all of their colliders, closest to the
+// player first.
+fn bake_chunks(
+    mut grid: ResMut<NavGrid>,
+    settings: Res<NavSettings>,
+    config: Res<WorldGenConfig>,
+    rapier: Res<RapierContext>,
+    player: Query<&Transform, With<Player>>,
+    children: Query<&Children>,
+    colliders: Query<(), With<Collider>>,
+){
+    let chunk_cells = (config.chunk_size as f32 / settings.cell_size).round() as i32;
+    if grid.chunk_cells != chunk_cells || grid.cell_size != settings.cell_size {
+        grid.chunks.clear();
+        let baked: Vec<_> = grid.baked.drain().collect();
+        grid.pending.extend(baked);
+        grid.chunk_cells = chunk_cells;
+        grid.cell_size = settings.cell_size;
+        grid.version += 1;
+    }
+    if grid.pending.is_empty() {
+        return;
+    }
+
+    if let Ok(transform) = player.get_single() {
+        let center = config.chunk_coords(transform.translation);
+        let distance = |coords: (i32, i32)| (coords.0 - center.0).abs().max((coords.1 - center.1).abs());
+        grid.pending.sort_by_key(|(_, coords)| std::cmp::Reverse(distance(*coords)));
+    }
+
+    let max_slope_cos = settings.max_slope.to_radians().cos();
+    let half_height = (settings.agent_height - settings.max_step).max(0.1) / 2.;
+    let agent = Collider::cylinder(half_height, settings.agent_radius);
+    let filter = QueryFilter::only_fixed().exclude_sensors();
+
+    let mut baked = 0;
+    let mut index = grid.pending.len();
+    while index > 0 && baked < settings.bakes_per_frame {
+        index -= 1;
+        let (entity, coords) = grid.pending[index];
+
+        let ready = children.iter_descendants(entity)
+            .filter(|child| colliders.contains(*child))
+            .all(|child| rapier.entity2collider().contains_key(&child));
+        if !ready {
+            continue;
+        }
+        grid.pending.remove(index);
+
+        let cells = chunk_cells as usize;
+        let mut nav = ChunkNav { walkable: vec![false; cells * cells], height: vec![0.; cells * cells] };
+        for lz in 0..chunk_cells {
+            for lx in 0..chunk_cells {
+                let x = ((coords.0 * chunk_cells + lx) as f32 + 0.5) * settings.cell_size;
+                let z = ((coords.1 * chunk_cells + lz) as f32 + 0.5) * settings.cell_size;
+                let Some((_, hit)) = rapier.cast_ray_and_get_normal(Vec3::new(x, RAY_TOP, z), Vec3::NEG_Y, RAY_TOP * 2., true, filter) else {
+                    continue;
+                };
+                let i = (lz * chunk_cells + lx) as usize;
+                nav.height[i] = hit.point.y;
+                if hit.normal.y < max_slope_cos {
+                    continue;
+                }
+                let body = hit.point + Vec3::Y * (settings.max_step + half_height);
+                nav.walkable[i] = rapier.intersection_with_shape(body, Quat::IDENTITY, &agent, filter).is_none();
+            }
+        }
+
+        grid.chunks.insert(coords, nav);
+        grid.baked.insert(entity, coords);
+        grid.version += 1;
+        baked += 1;
+    }
+}
+
+// Chasing mobs outside the flow field get a new path when the player changed
+// cell or theirs got old. Searches are capped per frame, the oldest paths go first.
+fn plan_paths(
+    time: Res<Time>,
+    grid: Res<NavGrid>,
+    flow: Res<FlowField>,
+    settings: Res<NavSettings>,
+    player: Query<&Transform, (With<Player>, Without<Mob>)>,
+    mut mobs: Query<(&Transform, &MobState, &mut NavPath), With<Mob>>,
+){
+    let Ok(player_transform) = player.get_single() else {
+        return;
+    };
+    let target = player_transform.translation;
+    let goal = grid.cell_of(target);
+    let dt = time.delta_seconds();
+
+    let mut stale = Vec::new();
+    for (transform, state, mut path) in mobs.iter_mut() {
+        path.age += dt;
+        if *state != MobState::Chase || flow.direction_at(transform.translation).is_some() {
+            path.waypoints.clear();
+            path.goal = None;
+            continue;
+        }
+        if path.goal != Some(goal) || path.age > settings.repath_interval {
+            stale.push((path.age, transform.translation, path));
+        }
+    }
+
+    stale.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, position, mut path) in stale.into_iter().take(settings.paths_per_frame) {
+        path.waypoints = grid.find_path(position, target, settings.max_step, settings.max_search_nodes).unwrap_or_default();
+        path.goal = Some(goal);
+        path.age = 0.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::NavGrid;
+
+    const MAX_STEP: f32 = 0.5;
+    const MAX_NODES: usize = 4000;
+
+    // one chunk of 32 x 32 cells split by a wall along x = 10
+    fn walled(gap: Option<i32>) -> NavGrid {
+        let wall: Vec<IVec2> = (0..32).filter(|&z| Some(z) != gap).map(|z| IVec2::new(10, z)).collect();
+        NavGrid::flat(1., 32, [(0, 0)], &wall)
+    }
+
+    fn center(x: i32, z: i32) -> Vec3 {
+        Vec3::new(x as f32 + 0.5, 0., z as f32 + 0.5)
+    }
+
+    #[test]
+    fn straight_path_on_open_ground() {
+        let grid = NavGrid::flat(1., 32, [(0, 0)], &[]);
+        let path = grid.find_path(center(2, 5), center(20, 5), MAX_STEP, MAX_NODES).unwrap();
+        assert_eq!(path, vec![center(20, 5)]);
+    }
+
+    #[test]
+    fn path_goes_through_the_gap() {
+        let grid = walled(Some(20));
+        let path = grid.find_path(center(2, 5), center(20, 5), MAX_STEP, MAX_NODES).unwrap();
+        assert_eq!(path.last(), Some(&center(20, 5)));
+
+        // the stretches between the corners stay on walkable cells
+        let mut from = center(2, 5);
+        for &to in path.iter() {
+            for i in 0..=100 {
+                let point = from.lerp(to, i as f32 / 100.);
+                assert!(grid.is_walkable(grid.cell_of(point)), "{:?} crosses the wall at {}", path, point);
+            }
+            from = to;
+        }
+    }
+
+    #[test]
+    fn blocked_goal_gives_the_closest_cell() {
+        let grid = walled(None);
+        let path = grid.find_path(center(2, 5), center(20, 5), MAX_STEP, MAX_NODES).unwrap();
+        let last = path.last().unwrap();
+        assert!(last.x < 10., "{:?} went through the wall", path);
+        assert_eq!(grid.cell_of(*last), IVec2::new(9, 5));
+    }
+
+    #[test]
+    fn no_corner_cutting() {
+        let grid = NavGrid::flat(1., 32, [(0, 0)], &[IVec2::new(5, 6)]);
+        let neighbours: Vec<IVec2> = grid.neighbours(IVec2::new(5, 5), MAX_STEP).map(|(cell, _)| cell).collect();
+        assert!(!neighbours.contains(&IVec2::new(6, 6)));
+        assert!(!neighbours.contains(&IVec2::new(4, 6)));
+        assert!(neighbours.contains(&IVec2::new(6, 4)));
+    }
+}
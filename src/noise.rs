@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+// Seeded value noise summed over a few octaves. Sampled in world coordinates,
+// so neighbouring chunks agree on the heights along their shared border.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct FractalNoise {
+    // comes from the WorldSeed, never from a config file
+    #[serde(skip)]
+    pub seed: u32,
+    pub octaves: u32,
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub lacunarity: f32,
+    pub persistence: f32,
+}
+
+impl Default for FractalNoise {
+    fn default() -> Self {
+        FractalNoise {
+            seed: 0,
+            octaves: 4,
+            frequency: 1. / 64.,
+            amplitude: 4.,
+            lacunarity: 2.,
+            persistence: 0.5,
+        }
+    }
+}
+
+impl FractalNoise {
+
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let mut frequency = self.frequency;
+        let mut amplitude = self.amplitude;
+        let mut sum = 0.;
+
+        for octave in 0..self.octaves {
+            let seed = self.seed.wrapping_add(octave.wrapping_mul(0x9E37_79B9));
+            sum += value_noise(x * frequency, z * frequency, seed) * amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+        sum
+    }
+
+    // finite difference normal, continuous across chunk borders
+    pub fn normal(&self, x: f32, z: f32) -> Vec3 {
+        let eps = 0.5;
+        let dx = self.sample(x + eps, z) - self.sample(x - eps, z);
+        let dz = self.sample(x, z + eps) - self.sample(x, z - eps);
+        Vec3::new(-dx, 2. * eps, -dz).normalize()
+    }
+}
+
+fn value_noise(x: f32, z: f32, seed: u32) -> f32 {
+    let x0 = x.floor();
+    let z0 = z.floor();
+    let tx = smooth(x - x0);
+    let tz = smooth(z - z0);
+    let (x0, z0) = (x0 as i32, z0 as i32);
+
+    let a = lattice(x0, z0, seed);
+    let b = lattice(x0 + 1, z0, seed);
+    let c = lattice(x0, z0 + 1, seed);
+    let d = lattice(x0 + 1, z0 + 1, seed);
+
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * tz
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+// value in [-1, 1] for an integer lattice point
+fn lattice(x: i32, z: i32, seed: u32) -> f32 {
+    let h = hash(x as u32, z as u32, seed);
+    (h as f32 / u32::MAX as f32) * 2. - 1.
+}
+
+pub fn hash(x: u32, z: u32, seed: u32) -> u32 {
+    let mut h = seed ^ 0x27d4_eb2d;
+    h = (h ^ x).wrapping_mul(0x85eb_ca6b);
+    h = h.rotate_left(13);
+    h = (h ^ z).wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FractalNoise;
+
+    fn noise(seed: u32) -> FractalNoise {
+        FractalNoise { seed, ..FractalNoise::default() }
+    }
+
+    #[test]
+    fn same_seed_same_heights() {
+        let (a, b) = (noise(7), noise(7));
+        for i in 0..100 {
+            let (x, z) = (i as f32 * 3.7 - 150., i as f32 * -5.3 + 40.);
+            assert_eq!(a.sample(x, z), b.sample(x, z));
+        }
+    }
+
+    #[test]
+    fn seeds_change_the_heights() {
+        let (a, b) = (noise(1), noise(2));
+        let differ = (0..100).any(|i| a.sample(i as f32 * 7.1, i as f32 * 3.3) != b.sample(i as f32 * 7.1, i as f32 * 3.3));
+        assert!(differ);
+    }
+
+    #[test]
+    fn stays_within_the_octave_amplitudes() {
+        let noise = noise(3);
+        let bound: f32 = (0..noise.octaves).map(|octave| noise.amplitude * noise.persistence.powi(octave as i32)).sum();
+        for i in 0..1000 {
+            let height = noise.sample(i as f32 * 1.3 - 600., i as f32 * 0.7 - 300.);
+            assert!(height.abs() <= bound, "{} out of ±{}", height, bound);
+        }
+    }
+
+    // the chunks sample in world space, a border only matches if the field is continuous
+    #[test]
+    fn continuous_across_lattice_lines() {
+        let noise = noise(4);
+        for x in -20..20 {
+            let x = x as f32 / noise.frequency;
+            let step = (noise.sample(x - 0.001, 10.) - noise.sample(x + 0.001, 10.)).abs();
+            assert!(step < 0.01, "jump of {} at x = {}", step, x);
+        }
+    }
+}
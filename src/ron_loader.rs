@@ -0,0 +1,91 @@
+use std::marker::PhantomData;
+
+use bevy::{asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext}, prelude::*, utils::BoxedFuture};
+use serde::de::DeserializeOwned;
+
+// rejects a deserialized asset with the reason why
+pub type Validation<T> = fn(&T) -> Result<(), String>;
+
+// Reads any asset written as RON, registered once per asset type with the
+// extensions of its files.
+pub struct RonAssetLoader<T> {
+    extensions: &'static [&'static str],
+    // a rejected file fails its load, so a hot reload keeps the previous asset
+    validate: Option<Validation<T>>,
+    asset: PhantomData<fn() -> T>,
+}
+
+impl<T> RonAssetLoader<T> {
+
+    pub fn new(extensions: &'static [&'static str]) -> Self {
+        RonAssetLoader {
+            extensions,
+            validate: None,
+            asset: PhantomData,
+        }
+    }
+
+    pub fn with_validation(mut self, validate: Validation<T>) -> Self {
+        self.validate = Some(validate);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum RonAssetError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Invalid(String),
+}
+
+impl std::fmt::Display for RonAssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RonAssetError::Io(error) => write!(f, "could not read asset: {error}"),
+            RonAssetError::Ron(error) => write!(f, "invalid asset: {error}"),
+            RonAssetError::Invalid(reason) => write!(f, "rejected asset: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RonAssetError {}
+
+impl From<std::io::Error> for RonAssetError {
+    fn from(error: std::io::Error) -> Self {
+        RonAssetError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for RonAssetError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        RonAssetError::Ron(error)
+    }
+}
+
+impl<T: Asset + DeserializeOwned> AssetLoader for RonAssetLoader<T> {
+    type Asset = T;
+    type Settings = ();
+    type Error = RonAssetError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let asset: T = ron::de::from_bytes(&bytes)?;
+            if let Err(reason) = self.validate.map_or(Ok(()), |validate| validate(&asset)) {
+                warn!("{}: {}, keeping the previous version", load_context.path().display(), reason);
+                return Err(RonAssetError::Invalid(reason));
+            }
+            Ok(asset)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
@@ -0,0 +1,52 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{noise::hash, utils::launch_argument};
+
+const DEFAULT_SEED: u64 = 0x005E_ED0F_4E11;
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        WorldSeed(DEFAULT_SEED)
+    }
+}
+
+impl WorldSeed {
+
+    // native: `--seed 42` or `--seed=42`, wasm: `?seed=42` in the page url.
+    // Non numeric seeds are hashed so `?seed=lava` is valid too.
+    pub fn from_env() -> Self {
+        match launch_argument("seed") {
+            Some(arg) => {
+                let seed = Self::parse(&arg);
+                info!("world seed: {} ({})", seed.0, arg);
+                seed
+            },
+            None => Self::default(),
+        }
+    }
+
+    pub fn parse(arg: &str) -> Self {
+        match arg.trim().parse::<u64>() {
+            Ok(seed) => WorldSeed(seed),
+            Err(_) => WorldSeed(arg.trim().bytes().fold(0xcbf2_9ce4_8422_2325, |h: u64, b| {
+                (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+            })),
+        }
+    }
+
+    pub fn noise_seed(&self) -> u32 {
+        (self.0 ^ (self.0 >> 32)) as u32
+    }
+
+    // Only depends on the seed and the chunk coordinates, so a chunk is laid out
+    // the same way whatever order chunks get streamed in.
+    pub fn chunk_rng(&self, x: i32, z: i32) -> StdRng {
+        let low = hash(x as u32, z as u32, self.0 as u32) as u64;
+        let high = hash(z as u32, x as u32, (self.0 >> 32) as u32) as u64;
+        StdRng::seed_from_u64(self.0 ^ (high << 32 | low))
+    }
+}
@@ -1,18 +1,58 @@
+use bevy::prelude::*;
+use bevy_rapier3d::{dynamics::{ExternalImpulse, LockedAxes, RigidBody, Sleeping, Velocity}, geometry::Collider};
+use rand::Rng;
+
+use crate::{animation::{AnimState, AnimationController, Clip}, asset_loader::GameAssets, health::{Health, Mortal}, mob_state::{Behaviour, MobBrain, MobState}, navigation::NavPath, steering::{steer_mobs, Steering}, world::{Player, Terrain}};
+
+pub const LING_SCENE: &str = "models/spike_ling_0.glb#Scene0";
+const LING_RUN: &str = "models/spike_ling_0.glb#Animation1";
+
+#[derive(Resource)]
+pub struct SpawnerConfig {
+    pub spawners: usize,
+    // spawners are scattered on this ring around the player, and moved back
+    // onto it once the player leaves them behind
+    pub ring: (f32, f32),
+    // spawners and mobs further than this are moved back onto the ring or despawned
+    pub recycle_radius: f32,
+    // spawners only produce mobs while the player is this close
+    pub activation_radius: f32,
+    // seconds between two batches of a spawner, picked at random in this range
+    pub interval: (f32, f32),
+    pub batch_size: usize,
+    pub max_alive: usize,
+    // mobs further than this are hidden and put to sleep
+    pub mob_sleep_radius: f32,
+    pub mob_radius: f32,
+}
 
+impl Default for SpawnerConfig {
+    fn default() -> Self {
+        SpawnerConfig {
+            spawners: 200,
+            ring: (20., 150.),
+            recycle_radius: 160.,
+            activation_radius: 100.,
+            interval: (3., 5.),
+            batch_size: 4,
+            max_alive: 300,
+            mob_sleep_radius: 50.,
+            mob_radius: 0.5,
+        }
+    }
+}
 
-#[derive(Component)]
-pub struct TestStart(pub usize);
-
-struct SpawnerPlugin;
+pub struct SpawnerPlugin<S: States> {
+    pub state: S,
+}
 
-impl Plugin for SpawnerPlugin {
+impl<S: States> Plugin for SpawnerPlugin<S> {
     fn build(&self, app: &mut App){
-        
+
         app
-        .init_resource::<EntityTypes>()
-        .add_systems(Update, update_spawner)
-        //.add_systems(Update, (spawning, update_mobs, tmp_animation))
-        .add_systems(Update, (link_animations, map_entity_to_type));
+        .init_resource::<SpawnerConfig>()
+        .add_systems(OnEnter(self.state.clone()), add_spawner)
+        .add_systems(Update, (update_spawner, spawning, update_mobs, steer_mobs).chain().run_if(in_state(self.state.clone())));
     }
 }
 
@@ -25,272 +65,207 @@ struct SpawnerBundle {
 }
 
 #[derive(Component)]
-struct Spawner(pub f32);
+pub struct Spawner {
+    pub timer: f32,
+    pub next_batch: f32,
+    pub active: bool,
+}
+
+#[derive(Component)]
+pub struct Mob;
 
 #[derive(Component)]
-struct Mob;
+pub struct Ling;
 
-fn update_mobs(
+type PlayerAndMobs<'w, 's> = ParamSet<'w, 's, (
+    Query<'static, 'static, &'static Transform, With<Player>>,
+    Query<'static, 'static, (Entity, &'static Transform, &'static mut Sleeping, &'static mut Visibility), With<Mob>>,
+)>;
 
-    mut transforms: ParamSet<(
-        Query<&Transform, With<Player>>,
-        Query<(&mut Transform, &mut Velocity, &mut Sleeping, &mut Visibility), With<Mob>>
-    )>,
+// far away mobs are hidden and taken out of the simulation, steering only
+// moves the awake ones; the ones left behind past the recycle radius are
+// despawned so they do not count against max_alive forever
+fn update_mobs(
+    mut commands: Commands,
+    mut transforms: PlayerAndMobs,
+    config: Res<SpawnerConfig>,
 ){
-    let player_position = transforms.p0().single().translation;
-    for (mut mob_transform, mut velocity, mut sleeping, mut visibility) in transforms.p1().iter_mut() {
-        
-        if player_position.distance(mob_transform.translation) > 50. {
+    let Ok(player_transform) = transforms.p0().get_single().cloned() else {
+        return;
+    };
+    let player_position = player_transform.translation;
+    for (mob, mob_transform, mut sleeping, mut visibility) in transforms.p1().iter_mut() {
+
+        let distance = player_position.distance(mob_transform.translation);
+        if distance > config.recycle_radius {
+            commands.entity(mob).despawn_recursive();
+        } else if distance > config.mob_sleep_radius {
             *visibility = Visibility::Hidden;
             sleeping.sleeping = true;
-            //*material = materials.materials[1].clone();
         } else if sleeping.sleeping {
             sleeping.sleeping = false;
             *visibility = Visibility::Visible;
-            //*material = materials.materials[0].clone();
-        }
-
-        if !sleeping.sleeping {
-            mob_transform.look_at(player_position, Vec3::Y);
-            let mut delta = player_position - mob_transform.translation;
-            delta.y = 0.;
-            velocity.linvel = delta.normalize() * 3.;
         }
     }
 }
 
 
-#[derive(Default, Resource)]
-struct EntityTypes(pub HashMap<Entity, EntityType>);
-
-enum EntityType {
-    Player,
-    Ling,
+// the ling only has a running cycle, the states differ by pace
+fn ling_animations() -> AnimationController {
+    AnimationController::default()
+    .with_clip(AnimState::Idle, Clip::new(LING_RUN).speed(0.5))
+    .with_clip(AnimState::Walk, Clip::new(LING_RUN).scaled_by_velocity(1.5))
+    .with_clip(AnimState::Run, Clip::new(LING_RUN).speed(2.).scaled_by_velocity(3.))
+    .with_clip(AnimState::Attack, Clip::new(LING_RUN).speed(5.).crossfade(0.1))
+    .with_state(AnimState::Idle)
 }
 
-fn map_entity_to_type(
-    mut entities: ParamSet<(
-        Query<(Entity, &Player), Added<Player>>,
-        Query<(Entity, &Ling), Added<Ling>>,
-    )>,
-    mut entity_type_map: ResMut<EntityTypes>,
-){
-    for (entity, _) in entities.p0().iter() {
-        entity_type_map.0.insert(entity, EntityType::Player);
-    }
-
-    for (entity, _) in entities.p1().iter() {
-        entity_type_map.0.insert(entity, EntityType::Player);
-    }
-}
-
-#[derive(Component)]
-pub struct AnimationEntityLink(pub Entity);
-
-fn get_top_parent(
-    mut curr_entity: Entity,
-    parent_query: &Query<&Parent>
-) -> Entity {
-    //Loop up all the way to the top parent
-    loop {
-        if let Ok(parent) = parent_query.get(curr_entity) {
-            curr_entity = parent.get();
-        } else {
-            break;
-        }
-    }
-    curr_entity
-}
-
-pub fn link_animations(
-    player_query: Query<Entity, Added<AnimationPlayer>>,
-    parent_query: Query<&Parent>,
-    animations_entity_link_query: Query<&AnimationEntityLink>,
-    mut commands: Commands,
-) {
-    // Get all the Animation players which can be deep and hidden in the heirachy
-    for entity in player_query.iter() {
-        let top_entity = get_top_parent(entity, &parent_query);
-
-        // If the top parent has an animation config ref then link the player to the config
-        if animations_entity_link_query.get(top_entity).is_ok() {
-            warn!("Problem with multiple animationsplayers for the same top parent");
-        } else {
-
-            commands
-                .entity(top_entity)
-                .insert(AnimationEntityLink(entity.clone()));
-        }
-    }
-}
-
-fn tmp_animation(
-    mut player_query: Query<&mut AnimationPlayer>,
-    query: Query<(Entity, &AnimationEntityLink), Added<AnimationEntityLink>>,
-    animations: Res<AnimationAssets>,
-    entity_type_map: Res<EntityTypes>,
-){
-    for (top_entity, player_entity) in query.iter() {
-        match entity_type_map.0.get(&top_entity){
-            Some(e_type) => match e_type{
-                EntityType::Player => {
-                    if let Ok(mut player) = player_query.get_mut(player_entity.0){
-                        player.play(animations.animations[1].clone_weak()).set_speed(0.8).repeat();
-                    }
-                }
-                EntityType::Ling => {
-                    if let Ok(mut player) = player_query.get_mut(player_entity.0){
-                        player.play(animations.animations[0].clone_weak()).set_speed(2.).repeat();
-                    }
-                }
-            },
-            None => (),
-        }
-    }
+pub fn spawn_ling(commands: &mut Commands, scene: Handle<Scene>, transform: Transform, radius: f32) -> Entity {
+    commands.spawn((Mob, Ling, Steering::ling(), MobState::Idle, MobBrain::new(Behaviour::ling()), Health::new(30.), NavPath::default(), ling_animations()))
+    .insert(SceneBundle {
+        scene,
+        transform,
+        visibility: Visibility::Hidden,
+        ..default()
+    })
+    .insert(RigidBody::Dynamic)
+    .insert(Collider::ball(radius))
+    .insert(LockedAxes::ROTATION_LOCKED)
+    .insert(Velocity {
+        linvel: Vec3::ZERO,
+        angvel: Vec3::ZERO
+    })
+    .insert(ExternalImpulse::default())
+    .insert(Sleeping {
+        sleeping: true,
+        ..default()
+    })
+    .insert(Mortal {
+        despawn_after: Some(2.),
+        drop: None,
+    })
+    .id()
 }
 
 fn spawning(
     time: Res<Time>,
     mut commands: Commands,
-    mut spawners: Query<(&Transform, &mut Spawner, &Sleeping)>,
-    mut mobs: Local<(f32, u32)>,
-
-    // materials: ResMut<ColorMaterialAssets>,
-    // meshes: ResMut<MeshAssets>,
-    scenes: ResMut<ScenesAssets>,
+    mut spawners: Query<(&Transform, &mut Spawner)>,
+    mobs: Query<(), With<Mob>>,
+    mut report: Local<f32>,
+    config: Res<SpawnerConfig>,
+    assets: Res<GameAssets>,
 ) {
-    mobs.0 += time.delta_seconds();
-
+    *report += time.delta_seconds();
 
-    let t = Transform::from_scale(Vec3::ONE * 100.);
+    let Some(scene) = assets.get::<Scene>(LING_SCENE) else {
+        return;
+    };
 
     let mut rng = rand::thread_rng();
-    let range = 3.0..5.0;
-
-    for (transform, mut spawner, sleeping) in spawners.iter_mut() {
-        if !sleeping.sleeping
-        {
-            spawner.0 += time.delta_seconds();
-
-            let rng_r = rng.gen_range(range.clone());
-    
-            if spawner.0 > rng_r {
-    
-                let mut count = 0;
-                let radius: f32 = 0.5;
-                for _ in 0..4 {
-                    let mut t =  transform.clone();
-                    t.translation += Vec3::X * 1.1 * count as f32 * radius;
-                    commands.spawn(Mob)
-                    .insert(SceneBundle {
-                        scene: scenes.scenes[0].clone(),
-                        transform: t,
-                        visibility: Visibility::Hidden,
-                        ..default()
-                    })
-                    .insert(TestStart(0))
-                    .insert(RigidBody::Dynamic)
-                    .insert(Collider::ball(radius))
-                    .insert(LockedAxes::ROTATION_LOCKED | LockedAxes::TRANSLATION_LOCKED_Y)
-                    .insert(Velocity {
-                        linvel: Vec3::ZERO,
-                        angvel: Vec3::ZERO
-                    })
-                    .insert(Sleeping {
-                        sleeping: true,
-                        ..default()
-                    });
-                    count += 1;
-                    
-                }
-                mobs.1 += 4;
-                *spawner = Spawner(0.);
-            }
+    let mut alive = mobs.iter().count();
+
+    for (transform, mut spawner) in spawners.iter_mut() {
+        if !spawner.active {
+            continue;
         }
+
+        spawner.timer += time.delta_seconds();
+        if spawner.timer < spawner.next_batch || alive + config.batch_size > config.max_alive {
+            continue;
+        }
+
+        for count in 0..config.batch_size {
+            let mut t = *transform;
+            t.translation += Vec3::X * 2.2 * count as f32 * config.mob_radius + Vec3::Y * config.mob_radius;
+            spawn_ling(&mut commands, scene.clone(), t, config.mob_radius);
         }
-        
-    if mobs.0 > 3. {
-        info!("Mobs: {}", mobs.1);
-        mobs.0 -= 5.;
+        alive += config.batch_size;
+        spawner.timer = 0.;
+        spawner.next_batch = rng.gen_range(config.interval.0..=config.interval.1);
+    }
+
+    if *report > 5. {
+        info!("Mobs: {}", alive);
+        *report = 0.;
     }
-    
+
 }
 
+type PlayerAndSpawners<'w, 's> = ParamSet<'w, 's, (
+    Query<'static, 'static, &'static Transform, With<Player>>,
+    Query<'static, 'static, (&'static mut Spawner, &'static mut Transform)>,
+)>;
+
 fn update_spawner(
-    
-    mut transforms: ParamSet<(
-        Query<&Transform, With<Player>>,
-        Query<(&Spawner, &mut Transform, &mut Sleeping)>,
-    )>
-){
-    let mut player_transform = transforms.p0().single().clone();
-    player_transform.translation.y = 1.;
-    let player_position = player_transform.translation;
 
-    for (_, spawner_transform, mut sleeping) in transforms.p1().iter_mut(){
-        if player_position.distance(spawner_transform.translation) > 100. {
-            sleeping.sleeping = true;
-        } else if sleeping.sleeping {
-            sleeping.sleeping = false;
-        }
-    }
+    mut transforms: PlayerAndSpawners,
+    config: Res<SpawnerConfig>,
+    terrain: Res<Terrain>,
+){
+    let Ok(player_transform) = transforms.p0().get_single().cloned() else {
+        return;
+    };
+    let mut player_position = player_transform.translation;
+    player_position.y = 0.;
 
+    let mut rng = rand::thread_rng();
 
+    for (mut spawner, mut spawner_transform) in transforms.p1().iter_mut(){
+        let mut flat_position = spawner_transform.translation;
+        flat_position.y = 0.;
+        let mut distance = player_position.distance(flat_position);
 
-    // for (_, mut transform) in transforms.p1().iter_mut() {
-    //     if transform.translation.distance(player_transform.translation) > 40. {
+        // left behind, put it back on the ring around the player
+        if distance > config.recycle_radius {
+            *spawner_transform = ring_transform(player_position, &config, &terrain, &mut rng);
+            spawner.timer = 0.;
+            distance = config.ring.0;
+        }
 
-    //         let range = 35.0..45.;
+        spawner.active = distance <= config.activation_radius;
+    }
+}
 
-    //         let radius = rng.gen_range(range);
-    //         let angle:f32 = rng.gen_range(0.0..2.0 *3.1417);
-            
-    //         let mut spawner_transform = player_transform.clone();
-    //         spawner_transform.translation.x += radius;
-    //         spawner_transform.rotate_around(player_transform.translation, Quat::from_rotation_y(angle));
+fn ring_transform(center: Vec3, config: &SpawnerConfig, terrain: &Terrain, rng: &mut impl Rng) -> Transform {
+    let radius = rng.gen_range(config.ring.0..config.ring.1);
+    let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
 
-    //         *transform = spawner_transform;
-    //     }
-    // }
+    let point = center + Quat::from_rotation_y(angle) * (Vec3::X * radius);
+    Transform::from_xyz(point.x, terrain.height_at(point.x, point.z), point.z)
 }
 
 fn add_spawner(
 
     mut commands: Commands,
     player_transform: Query<&Transform, With<Player>>,
+    spawners: Query<(), With<Spawner>>,
+    config: Res<SpawnerConfig>,
+    terrain: Res<Terrain>,
 
 ){
+    // coming back from the pause menu, the spawners are still there
+    if !spawners.is_empty() {
+        return;
+    }
+    let Ok(transform) = player_transform.get_single() else {
+        return;
+    };
     let mut rng = rand::thread_rng();
 
-    let transform = player_transform.single().clone();
-    for _ in 0..200 {
-        let mut sleeping = true;
-        let range = 20.0..150.0;
-        let radius = rng.gen_range(range);
-
-        if radius > 75. {
-            sleeping = true;
-        }
-
-        let angle:f32 = rng.gen_range(0.0..2.0 *3.1417);
-        
-        let translation = Vec3::X * radius;
-        let point = transform.translation + translation;
-        let mut spawner_transform = Transform::from_xyz(point.x, point.y, point.z);
-        spawner_transform.rotate_around(transform.translation, Quat::from_rotation_y(angle));
-        //spawner_transform .translation += Vec3::Y;
+    for _ in 0..config.spawners {
+        let spawner_transform = ring_transform(transform.translation, &config, &terrain, &mut rng);
 
         commands.spawn(SpawnerBundle{
             t: TransformBundle{
                 local: spawner_transform,
                 ..default()
             },
-            s: Spawner(0.)
-        }).
-        insert(Collider::ball(0.1))
-        .insert(Sleeping{
-            sleeping,
-            ..default()
-        }); 
+            s: Spawner {
+                timer: 0.,
+                next_batch: rng.gen_range(config.interval.0..=config.interval.1),
+                active: false,
+            }
+        });
     }
-}
\ No newline at end of file
+}
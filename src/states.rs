@@ -6,7 +6,7 @@ use bevy::ecs::schedule::States;
 #[derive(States, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub enum GameState{
     #[default]
-    //LoadingGame,
+    Loading,
     InGame,
     Paused
 }
\ No newline at end of file
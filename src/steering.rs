@@ -0,0 +1,180 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::{dynamics::{Sleeping, Velocity}, pipeline::QueryFilter, plugin::RapierContext};
+use rand::Rng;
+
+use crate::{flow_field::FlowField, mob_state::MobState, navigation::NavPath, spawner::Mob, world::Player};
+
+// obstacles are the fixed colliders (terrain, decorations), the ray starts a bit
+// above the mob's origin so it does not scrape the ground it stands on
+const AVOID_RAY_HEIGHT: f32 = 0.3;
+// hits on surfaces flatter than this are ground, not walls
+const WALL_MAX_NORMAL_Y: f32 = 0.7;
+// distance at which a path waypoint counts as reached
+const WAYPOINT_REACH: f32 = 0.75;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SteeringWeights {
+    pub seek: f32,
+    pub separation: f32,
+    pub avoidance: f32,
+    pub wander: f32,
+}
+
+// Per mob steering parameters, each mob type gets its own constructor.
+#[derive(Component, Clone, Debug)]
+pub struct Steering {
+    pub max_speed: f32,
+    // how fast the velocity can turn toward the desired one, in m/s²
+    pub max_force: f32,
+    // mobs slow down inside this radius instead of ramming the player
+    pub arrive_radius: f32,
+    pub separation_radius: f32,
+    pub avoid_distance: f32,
+    // how much the wander heading can change per second, in radians
+    pub wander_jitter: f32,
+    pub weights: SteeringWeights,
+    wander_angle: f32,
+}
+
+impl Steering {
+
+    pub fn ling() -> Self {
+        Steering {
+            max_speed: 3.,
+            max_force: 12.,
+            arrive_radius: 1.5,
+            separation_radius: 1.2,
+            avoid_distance: 3.,
+            wander_jitter: 2.,
+            weights: SteeringWeights {
+                seek: 1.,
+                separation: 1.5,
+                avoidance: 2.,
+                wander: 0.5,
+            },
+            wander_angle: rand::thread_rng().gen_range(0.0..std::f32::consts::TAU),
+        }
+    }
+}
+
+fn flat(v: Vec3) -> Vec3 {
+    Vec3::new(v.x, 0., v.z)
+}
+
+// awake mobs bucketed by cell, so separation only looks at close neighbours
+fn neighbour_grid(positions: &[(Entity, Vec3)], cell: f32) -> HashMap<(i32, i32), Vec<(Entity, Vec3)>> {
+    let mut grid: HashMap<(i32, i32), Vec<(Entity, Vec3)>> = HashMap::default();
+    for &(entity, position) in positions {
+        let key = ((position.x / cell).floor() as i32, (position.z / cell).floor() as i32);
+        grid.entry(key).or_default().push((entity, position));
+    }
+    grid
+}
+
+type SteeringItem = (Entity, &'static mut Transform, &'static mut Velocity, &'static Sleeping, &'static mut Steering, &'static MobState, Option<&'static mut NavPath>);
+
+pub fn steer_mobs(
+    time: Res<Time>,
+    rapier: Res<RapierContext>,
+    flow: Res<FlowField>,
+    player: Query<&Transform, (With<Player>, Without<Mob>)>,
+    mut mobs: Query<SteeringItem, With<Mob>>,
+){
+    let Ok(player_transform) = player.get_single() else {
+        return;
+    };
+    let player_position = player_transform.translation;
+    let dt = time.delta_seconds();
+    let mut rng = rand::thread_rng();
+
+    let awake: Vec<(Entity, Vec3)> = mobs.iter()
+        .filter(|(_, _, _, sleeping, _, state, _)| !sleeping.sleeping && **state != MobState::Dead)
+        .map(|(entity, transform, _, _, _, _, _)| (entity, transform.translation))
+        .collect();
+    let cell = mobs.iter().map(|(_, _, _, _, steering, _, _)| steering.separation_radius).fold(1., f32::max);
+    let grid = neighbour_grid(&awake, cell);
+
+    for (entity, mut transform, mut velocity, sleeping, mut steering, state, mut path) in mobs.iter_mut() {
+        if sleeping.sleeping || *state == MobState::Dead {
+            continue;
+        }
+        let position = transform.translation;
+        let weights = steering.weights;
+
+        // the state machine decides what the mob is going for
+        let to_player = flat(player_position - position);
+        let distance = to_player.length();
+        let mut desired = match state {
+            MobState::Chase => {
+                // follow the flow field, or the mob's own path outside of it,
+                // straight at the player on the last stretch
+                let heading = flow.direction_at(position).or_else(|| {
+                    let waypoint = path.as_mut().and_then(|path| path.next(position, WAYPOINT_REACH))?;
+                    Some(flat(waypoint - position).normalize_or_zero())
+                });
+                match heading {
+                    Some(heading) if distance > steering.arrive_radius * 2. => {
+                        heading * steering.max_speed * weights.seek
+                    }
+                    _ => {
+                        let speed = steering.max_speed * (distance / steering.arrive_radius).min(1.);
+                        to_player.normalize_or_zero() * speed * weights.seek
+                    }
+                }
+            }
+            MobState::Flee => -to_player.normalize_or_zero() * steering.max_speed * weights.seek,
+            MobState::Idle => {
+                steering.wander_angle += rng.gen_range(-1.0..1.0) * steering.wander_jitter * dt;
+                Quat::from_rotation_y(steering.wander_angle) * Vec3::X * steering.max_speed * weights.wander
+            }
+            // planted while winding up, only pushed around by the neighbours
+            MobState::Attack | MobState::Dead => Vec3::ZERO,
+        };
+
+        // separation from neighbouring mobs
+        let key = ((position.x / cell).floor() as i32, (position.z / cell).floor() as i32);
+        let mut push = Vec3::ZERO;
+        for dx in -1..=1 {
+            for dz in -1..=1 {
+                let Some(neighbours) = grid.get(&(key.0 + dx, key.1 + dz)) else {
+                    continue;
+                };
+                for &(other, other_position) in neighbours {
+                    let away = flat(position - other_position);
+                    let d = away.length();
+                    if other == entity || d >= steering.separation_radius || d <= f32::EPSILON {
+                        continue;
+                    }
+                    push += away / d * (1. - d / steering.separation_radius);
+                }
+            }
+        }
+        desired += push * steering.max_speed * weights.separation;
+
+        // obstacle avoidance, look ahead along the current heading
+        let heading = flat(velocity.linvel).try_normalize().or(desired.try_normalize());
+        if let Some(heading) = heading {
+            let origin = position + Vec3::Y * AVOID_RAY_HEIGHT;
+            let hit = rapier.cast_ray_and_get_normal(origin, heading, steering.avoid_distance, true, QueryFilter::only_fixed());
+            if let Some((_, hit)) = hit.filter(|(_, hit)| hit.normal.y < WALL_MAX_NORMAL_Y) {
+                let closeness = 1. - hit.point.distance(origin) / steering.avoid_distance;
+                // slide along the wall rather than only backing off it
+                let normal = flat(hit.normal).normalize_or_zero();
+                let along = heading - normal * heading.dot(normal);
+                desired += (normal + along) * closeness * steering.max_speed * weights.avoidance;
+            }
+        }
+
+        let desired = desired.clamp_length_max(steering.max_speed);
+        let current = flat(velocity.linvel);
+        let change = (desired - current).clamp_length_max(steering.max_force * dt);
+        // leave the vertical speed to gravity
+        velocity.linvel.x = current.x + change.x;
+        velocity.linvel.z = current.z + change.z;
+
+        let facing = if *state == MobState::Attack { to_player } else { flat(velocity.linvel) };
+        if facing.length_squared() > 0.01 {
+            transform.look_to(facing, Vec3::Y);
+        }
+    }
+}
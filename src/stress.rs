@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{asset_loader::GameAssets, flow_field::FlowField, spawner::{spawn_ling, Mob, SpawnerConfig, LING_SCENE}, utils::launch_argument, world::{Player, Terrain}};
+
+// 60 fps
+const FRAME_BUDGET_MS: f32 = 1000. / 60.;
+const REPORT_SECONDS: f32 = 5.;
+
+// Horde performance check: `--stress-mobs 1000` (`?stress-mobs=1000` on the
+// web) drops that many awake lings around the player and logs frame times
+// against the budget. Release builds only give meaningful numbers.
+#[derive(Resource)]
+struct StressTest {
+    mobs: usize,
+    elapsed: f32,
+    frames: u32,
+    total_ms: f32,
+    worst_ms: f32,
+}
+
+pub struct StressTestPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for StressTestPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        let Some(mobs) = launch_argument("stress-mobs").and_then(|mobs| mobs.parse().ok()) else {
+            return;
+        };
+        info!("stress test: {} mobs, frame budget {:.1}ms", mobs, FRAME_BUDGET_MS);
+
+        app
+        .insert_resource(StressTest { mobs, elapsed: 0., frames: 0, total_ms: 0., worst_ms: 0. })
+        .add_systems(OnEnter(self.state.clone()), spawn_horde)
+        .add_systems(Update, report_frame_times.run_if(in_state(self.state.clone())));
+    }
+}
+
+fn spawn_horde(
+    mut commands: Commands,
+    mut config: ResMut<SpawnerConfig>,
+    test: Res<StressTest>,
+    assets: Res<GameAssets>,
+    terrain: Res<Terrain>,
+    player: Query<&Transform, With<Player>>,
+    mobs: Query<(), With<Mob>>,
+){
+    // only once, not on every return from the pause menu
+    if !mobs.is_empty() {
+        return;
+    }
+    let (Ok(player), Some(scene)) = (player.get_single(), assets.get::<Scene>(LING_SCENE)) else {
+        warn!("stress test: no player or ling scene, nothing spawned");
+        return;
+    };
+
+    // the spawners keep the count where it is
+    config.max_alive = test.mobs;
+
+    // inside the sleep radius so every mob is simulated
+    let mut rng = rand::thread_rng();
+    for _ in 0..test.mobs {
+        let radius = rng.gen_range(5.0..config.mob_sleep_radius * 0.9);
+        let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+        let point = player.translation + Quat::from_rotation_y(angle) * (Vec3::X * radius);
+        let ground = terrain.height_at(point.x, point.z) + config.mob_radius;
+        spawn_ling(&mut commands, scene.clone(), Transform::from_xyz(point.x, ground, point.z), config.mob_radius);
+    }
+}
+
+fn report_frame_times(
+    time: Res<Time>,
+    mut test: ResMut<StressTest>,
+    flow: Res<FlowField>,
+    mobs: Query<(), With<Mob>>,
+){
+    let frame_ms = time.delta_seconds() * 1000.;
+    test.elapsed += time.delta_seconds();
+    test.frames += 1;
+    test.total_ms += frame_ms;
+    test.worst_ms = test.worst_ms.max(frame_ms);
+
+    if test.elapsed < REPORT_SECONDS {
+        return;
+    }
+
+    let average = test.total_ms / test.frames as f32;
+    let report = format!(
+        "stress test: {} mobs, frame avg {:.2}ms worst {:.2}ms, flow field {:.2}ms, budget {:.1}ms",
+        mobs.iter().count(), average, test.worst_ms, flow.build_time.as_secs_f32() * 1000., FRAME_BUDGET_MS,
+    );
+    if average > FRAME_BUDGET_MS {
+        warn!("{} exceeded", report);
+    } else {
+        info!("{} met", report);
+    }
+
+    test.elapsed = 0.;
+    test.frames = 0;
+    test.total_ms = 0.;
+    test.worst_ms = 0.;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{prelude::*, scene::SceneSpawner};
+    use bevy_rapier3d::{dynamics::{RigidBody, Sleeping}, geometry::Collider, plugin::{NoUserData, RapierPhysicsPlugin}};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{flow_field::{FlowField, FlowFieldPlugin}, mob_state::MobState, navigation::{NavGrid, NavSettings}, spawner::{spawn_ling, Mob, SpawnerConfig}, states::GameState, steering::steer_mobs, world::Player};
+
+    use super::FRAME_BUDGET_MS;
+
+    const MOBS: usize = 1000;
+    // the colliders are created during the first ticks, they are not timed
+    const WARMUP_TICKS: u32 = 10;
+    const TICKS: u32 = 120;
+
+    fn spawn_horde(mut commands: Commands) {
+        let radius = SpawnerConfig::default().mob_radius;
+        commands.spawn((RigidBody::Fixed, Collider::cuboid(60., 0.5, 60.), TransformBundle::from(Transform::from_xyz(0., -0.5, 0.))));
+        commands.spawn((Player, TransformBundle::default()));
+
+        let mut rng = StdRng::seed_from_u64(1000);
+        for _ in 0..MOBS {
+            let distance = rng.gen_range(5.0..40.0);
+            let angle: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+            let position = Quat::from_rotation_y(angle) * Vec3::X * distance + Vec3::Y * radius;
+            let mob = spawn_ling(&mut commands, Handle::default(), Transform::from_translation(position), radius);
+            // inside the sleep radius, every mob is simulated
+            commands.entity(mob).insert((MobState::Chase, Sleeping::default()));
+        }
+    }
+
+    fn mean_distance(app: &mut App) -> f32 {
+        let mut mobs = app.world.query_filtered::<&Transform, With<Mob>>();
+        mobs.iter(&app.world).map(|transform| transform.translation.xz().length()).sum::<f32>() / MOBS as f32
+    }
+
+    // The flow field, the steering and the rapier step of a chasing horde on
+    // flat ground. Rendering is left out, the pipelined renderer runs it next
+    // to the following update. The dev profile optimizes the dependencies, so a
+    // plain `cargo test` checks the budget too; `cargo test --release
+    // thousand_mobs -- --nocapture` prints the timings of a shipped build.
+    #[test]
+    fn thousand_mobs_fit_the_frame_budget() {
+        let chunks = (-2..=2).flat_map(|x| (-2..=2).map(move |z| (x, z)));
+
+        let mut app = App::new();
+        app
+        .insert_state(GameState::InGame)
+        .init_resource::<Time>()
+        // rapier's async colliders look these up
+        .init_resource::<Assets<Mesh>>()
+        .init_resource::<SceneSpawner>()
+        .init_resource::<NavSettings>()
+        .insert_resource(NavGrid::flat(1., 50, chunks, &[]))
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugins(FlowFieldPlugin { state: GameState::InGame })
+        .add_systems(Startup, spawn_horde)
+        .add_systems(Update, steer_mobs);
+
+        let tick = |app: &mut App| {
+            app.world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(1. / 60.));
+            let start = Instant::now();
+            app.update();
+            start.elapsed()
+        };
+        for _ in 0..WARMUP_TICKS {
+            tick(&mut app);
+        }
+        let start_distance = mean_distance(&mut app);
+
+        let mut total = Duration::ZERO;
+        let mut worst = Duration::ZERO;
+        for _ in 0..TICKS {
+            let elapsed = tick(&mut app);
+            total += elapsed;
+            worst = worst.max(elapsed);
+        }
+
+        let flow = app.world.resource::<FlowField>();
+        assert!(flow.direction_at(Vec3::new(20., 0., 0.)).is_some(), "the flow field was never built");
+        let build_ms = flow.build_time.as_secs_f32() * 1000.;
+        let end_distance = mean_distance(&mut app);
+        assert!(end_distance < start_distance, "the horde did not close in: {:.1} then {:.1}", start_distance, end_distance);
+
+        let average_ms = total.as_secs_f32() * 1000. / TICKS as f32;
+        println!(
+            "{} mobs: tick avg {:.2}ms worst {:.2}ms, flow field {:.2}ms",
+            MOBS, average_ms, worst.as_secs_f32() * 1000., build_ms,
+        );
+        assert!(average_ms < FRAME_BUDGET_MS, "{} mobs take {:.2}ms a tick", MOBS, average_ms);
+    }
+}
@@ -1,3 +1,27 @@
+// native: `--name value` or `--name=value`, wasm: `?name=value` in the page url
+#[cfg(not(target_arch = "wasm32"))]
+pub fn launch_argument(name: &str) -> Option<String> {
+    let flag = format!("--{name}");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(&flag).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
 
-use bevy::prelude::*;
+#[cfg(target_arch = "wasm32")]
+pub fn launch_argument(name: &str) -> Option<String> {
+    let location = js_sys::Reflect::get(&js_sys::global(), &"location".into()).ok()?;
+    let search = js_sys::Reflect::get(&location, &"search".into()).ok()?.as_string()?;
+    let prefix = format!("{name}=");
 
+    search.trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(prefix.as_str()))
+        .map(|value| js_sys::decode_uri_component(value).map(String::from).unwrap_or(value.to_string()))
+}
@@ -0,0 +1,203 @@
+use bevy::{asset::{LoadState, RecursiveDependencyLoadState}, prelude::*};
+use bevy_rapier3d::geometry::{Collider, ColliderDisabled, ColliderMassProperties, Sensor};
+use serde::Deserialize;
+
+use crate::{actions::{Action, Actions}, animation::{AnimState, AnimationController, AnimationRoot, Clip}, asset_loader::GameAssets, camera::MainCamera, melee::Melee, ron_loader::RonAssetLoader, world::Player};
+
+// the Weapon actions pick the weapon in the same slot
+pub const WEAPON_SLOTS: [&str; 2] = [
+    "weapons/red_large_sword.weapon.ron",
+    "weapons/quick_red_sword.weapon.ron",
+];
+
+// width and height of the swing, the depth is the weapon's reach
+const HITBOX_HALF_SIZE: Vec2 = Vec2::new(0.8, 0.6);
+
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WeaponDefinition {
+    pub name: String,
+    pub scene: String,
+    pub idle_animation: Option<String>,
+    pub swing_animation: String,
+    // where the view model sits in front of the camera
+    pub view_offset: (f32, f32, f32),
+    pub damage: f32,
+    pub knockback: f32,
+    pub reach: f32,
+    // seconds from the click, the hitbox is only on between windup and windup + active
+    pub windup: f32,
+    pub active: f32,
+    pub swing: f32,
+    pub animation_speed: f32,
+    // another click this soon after a swing chains into the next one
+    pub combo_window: f32,
+    pub combo_steps: u32,
+    // extra damage per step of the combo
+    pub combo_bonus: f32,
+    // seconds after a swing (or the end of a combo) before a new attack
+    pub cooldown: f32,
+}
+
+impl WeaponDefinition {
+
+    fn animations(&self) -> AnimationController {
+        let mut controller = AnimationController::default()
+        .with_clip(AnimState::Attack, Clip::new(&self.swing_animation).speed(self.animation_speed).crossfade(0.05))
+        .with_state(AnimState::Idle);
+        if let Some(idle) = &self.idle_animation {
+            controller = controller.with_clip(AnimState::Idle, Clip::new(idle));
+        }
+        controller
+    }
+}
+
+impl Default for WeaponDefinition {
+    fn default() -> Self {
+        WeaponDefinition {
+            name: String::new(),
+            scene: String::new(),
+            idle_animation: None,
+            swing_animation: String::new(),
+            view_offset: (0.5, -0.6, -0.8),
+            damage: 10.,
+            knockback: 4.,
+            reach: 2.,
+            windup: 0.15,
+            active: 0.2,
+            swing: 0.6,
+            animation_speed: 1.,
+            combo_window: 0.4,
+            combo_steps: 1,
+            combo_bonus: 0.,
+            cooldown: 0.5,
+        }
+    }
+}
+
+// View model of the equipped weapon, under the MainCamera.
+#[derive(Component)]
+pub struct Weapon {
+    pub definition: Handle<WeaponDefinition>,
+}
+
+// Sensor in front of the camera, only enabled during the active frames.
+#[derive(Component)]
+pub struct WeaponHitbox;
+
+#[derive(Resource, Default)]
+pub struct Loadout {
+    pub selected: usize,
+    equipped: Option<usize>,
+    // world the weapon assets are requested under
+    world_handle: Option<usize>,
+}
+
+pub struct WeaponPlugin<S: States> {
+    pub state: S,
+}
+
+impl<S: States> Plugin for WeaponPlugin<S> {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_asset::<WeaponDefinition>()
+        .register_asset_loader(RonAssetLoader::<WeaponDefinition>::new(&["weapon.ron"]))
+        .init_resource::<Loadout>()
+        .add_systems(Update, select_weapon.run_if(in_state(self.state.clone())))
+        .add_systems(Update, attach_weapon.after(select_weapon));
+    }
+}
+
+fn select_weapon(
+    actions: Res<Actions>,
+    mut loadout: ResMut<Loadout>,
+){
+    for slot in 0..WEAPON_SLOTS.len() {
+        if actions.just_pressed(Action::Weapon(slot as u8)) && loadout.selected != slot {
+            loadout.selected = slot;
+        }
+    }
+}
+
+type EquippedFilter = Or<(With<Weapon>, With<WeaponHitbox>)>;
+
+// swaps the view model once the selected weapon and its scene are loaded, the
+// current one stays until then
+#[allow(clippy::too_many_arguments)]
+fn attach_weapon(
+    mut commands: Commands,
+    mut loadout: ResMut<Loadout>,
+    cameras: Query<Entity, With<MainCamera>>,
+    players: Query<Entity, With<Player>>,
+    equipped: Query<Entity, EquippedFilter>,
+    definitions: Res<Assets<WeaponDefinition>>,
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+    mut warned: Local<Option<usize>>,
+){
+    if loadout.equipped == Some(loadout.selected) {
+        return;
+    }
+    let (Ok(camera), Ok(player)) = (cameras.get_single(), players.get_single()) else {
+        return;
+    };
+    let selected = loadout.selected;
+    let world_handle = match loadout.world_handle {
+        Some(world_handle) => world_handle,
+        None => *loadout.world_handle.insert(assets.new_world()),
+    };
+
+    let handle = assets.request::<WeaponDefinition>(world_handle, WEAPON_SLOTS[selected], &server);
+    let Some(definition) = definitions.get(&handle) else {
+        if server.get_load_state(&handle) == Some(LoadState::Failed) && *warned != Some(selected) {
+            warn!("weapon {} could not be loaded", WEAPON_SLOTS[selected]);
+            *warned = Some(selected);
+        }
+        return;
+    };
+    let scene = assets.request::<Scene>(world_handle, &definition.scene, &server);
+    match server.get_recursive_dependency_load_state(&scene) {
+        Some(RecursiveDependencyLoadState::Loaded) => (),
+        Some(RecursiveDependencyLoadState::Failed) => {
+            if *warned != Some(selected) {
+                warn!("weapon {}: scene {} could not be loaded", definition.name, definition.scene);
+                *warned = Some(selected);
+            }
+            return;
+        }
+        _ => return,
+    }
+
+    for entity in equipped.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let (x, y, z) = definition.view_offset;
+    commands.entity(camera).with_children(|camera| {
+        camera.spawn((
+            Weapon { definition: handle },
+            AnimationRoot,
+            definition.animations(),
+            SceneBundle {
+                scene,
+                transform: Transform::from_xyz(x, y, z),
+                ..default()
+            },
+        ));
+        camera.spawn((
+            WeaponHitbox,
+            Sensor,
+            ColliderDisabled,
+            // part of the player's body, it must not weigh anything
+            ColliderMassProperties::Density(0.),
+            Collider::cuboid(HITBOX_HALF_SIZE.x, HITBOX_HALF_SIZE.y, definition.reach / 2.),
+            TransformBundle::from(Transform::from_xyz(0., 0., -definition.reach / 2.)),
+        ));
+    });
+
+    // no swing carries over to the new weapon
+    commands.entity(player).insert(Melee::ready(definition));
+    loadout.equipped = Some(loadout.selected);
+    info!("equipped {}", definition.name);
+}
@@ -1,12 +1,10 @@
-use bevy::prelude::*;
-use bevy_rapier3d::{control::{CharacterLength, KinematicCharacterController}, dynamics::{ExternalImpulse, LockedAxes, RigidBody, Velocity}, geometry::Collider};
-use rand::Rng;
+use bevy::{prelude::*, render::{texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor}, view::RenderLayers}, utils::HashMap};
+use bevy_rapier3d::{control::{CharacterLength, KinematicCharacterController}, dynamics::{ExternalImpulse, LockedAxes, RigidBody, Velocity}, geometry::Collider, plugin::RapierConfiguration};
 
-use crate::{asset_loader::GameAssets, camera::{CameraRotationVelocity, MainCamera, TopCamera}, flat_mesh::gen_flat_mesh};
+use crate::{asset_loader::GameAssets, body::BODY_LAYER, decoration::{scatter_points, CatalogueHandle, DecorationCatalogue}, camera::{CameraRotationVelocity, MainCamera, TopCamera}, character::{CharacterIntent, CharacterMotor}, flat_mesh::{gen_terrain_collider, gen_terrain_mesh, GridLayout}, health::Health, noise::FractalNoise, seed::WorldSeed, world_config::{WorldGenChanged, WorldGenConfig}};
 
-const CHUNK_RADIUS: i32 = 5;
-const CHUNK_SIZE: i32 = 50;
 const EYE: f32 = 2.;
+const GROUND_TEXTURE: &str = "textures/gravier_16px.png";
 
 #[derive(Component)]
 pub struct Player;
@@ -14,6 +12,61 @@ pub struct Player;
 #[derive(Component)]
 pub struct Chunk(pub i32, pub i32);
 
+#[derive(Component)]
+pub struct ChunkLod(pub u32);
+
+// The ground never waits for the decoration catalogue, chunks get their props
+// once it is loaded.
+#[derive(Component)]
+pub struct Decorated;
+
+#[derive(Default, Resource)]
+pub struct LoadedChunks {
+    world_handle: usize,
+    center: Option<(i32, i32)>,
+    chunks: HashMap<(i32, i32), Entity>,
+    // every chunk renders with the same material, only their meshes differ
+    material: Handle<StandardMaterial>,
+}
+
+#[derive(Resource)]
+pub struct Terrain(pub FractalNoise);
+
+impl FromWorld for Terrain {
+    fn from_world(world: &mut World) -> Self {
+        let seed = world.get_resource::<WorldSeed>().copied().unwrap_or_default();
+        let config = world.get_resource::<WorldGenConfig>().cloned().unwrap_or_default();
+        Terrain::new(&config, seed)
+    }
+}
+
+impl Terrain {
+    pub fn new(config: &WorldGenConfig, seed: WorldSeed) -> Self {
+        let mut noise = config.terrain;
+        noise.seed = seed.noise_seed();
+        Terrain(noise)
+    }
+
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        self.0.sample(x, z)
+    }
+}
+
+// The heights differ from chunk to chunk, so unlike the material the terrain
+// meshes cannot be shared; distant chunks get a coarser one instead.
+fn chunk_mesh(terrain: &Terrain, size: i32, x: i32, z: i32, lod: u32, ground_repeat: Option<f32>) -> Mesh {
+    let origin = Vec2::new((x * size) as f32, (z * size) as f32);
+    // world-space uvs, the tiling lines up across chunks
+    let layout = match ground_repeat {
+        Some(repeat) => GridLayout::Shared { repeat, uv_offset: origin },
+        None => GridLayout::PerCell,
+    };
+    gen_terrain_mesh(0, 0, size, size, lod, layout, |lx, lz| {
+        let (x, z) = (origin.x + lx, origin.y + lz);
+        (terrain.height_at(x, z), terrain.0.normal(x, z))
+    })
+}
+
 pub struct WorldPlugin<S: States> {
     pub state: S,
 }
@@ -21,9 +74,14 @@ impl<S: States> Plugin for WorldPlugin<S> {
     fn build(&self, app: &mut App) {
 
         app
-        .add_systems(Startup, (player_placement, world_builder).chain()
-        .run_if(in_state(self.state.clone())))
-        .add_systems(Update, tmp_anim_sword);
+        .insert_resource(WorldSeed::from_env())
+        .init_resource::<LoadedChunks>()
+        .init_resource::<Terrain>()
+        .add_systems(Startup, (player_placement, world_builder, pause_physics).chain())
+        .add_systems(OnExit(self.state.clone()), pause_physics)
+        .add_systems(Update, (rebuild_terrain, regenerate_chunks).chain().before(stream_chunks))
+        .add_systems(Update, repeat_ground_texture)
+        .add_systems(Update, (stream_chunks, decorate_chunks, resume_physics).chain().run_if(in_state(self.state.clone())));
     }
 }
 
@@ -32,10 +90,13 @@ pub fn player_placement (
     mut commands: Commands,
     mut assets: ResMut<GameAssets>,
     server: Res<AssetServer>,
+    terrain: Res<Terrain>,
+    config: Res<WorldGenConfig>,
 ) {
+    let spawn = config.chunk_size as f32 / 2.;
     commands
     .spawn((Player, SpatialBundle{
-        transform: Transform::from_xyz(CHUNK_SIZE as f32 / 2. , EYE / 2., CHUNK_SIZE as f32 / 2.),
+        transform: Transform::from_xyz(spawn, terrain.height_at(spawn, spawn) + EYE / 2., spawn),
         ..default()
     }))
     .insert(RigidBody::Dynamic)
@@ -45,6 +106,9 @@ pub fn player_placement (
         up: Vec3::Y,
         ..default()
     })
+    .insert(Health::new(100.).with_invulnerability(0.8))
+    .insert(CharacterIntent::default())
+    .insert(CharacterMotor::default())
     .insert(LockedAxes::ROTATION_LOCKED)
     .insert(Velocity {
         linvel: Vec3::ZERO,
@@ -64,6 +128,7 @@ pub fn player_placement (
         let mut top_camera_transform = Transform::from_xyz(0., 5., 0.);
         top_camera_transform.rotate_x(-3.1416 / 2.);
         player.spawn((TopCamera,
+            RenderLayers::layer(0).with(BODY_LAYER),
             Camera3dBundle{
                 camera: Camera {
                     is_active: false,
@@ -92,16 +157,19 @@ pub fn player_placement (
                     }),
                     ..default()
             }
-        )).with_children(|camera|{
+        ));
 
-            camera.spawn(DirectionalLightBundle {
-                transform: Transform::from_xyz(0., 50., -20.),
-                // directional_light: DirectionalLight {
-                //     ..default()
-                // },
+        // under the player rather than the camera so the shadows do not swing
+        // with the view, the body layer lets the runner cast one seen from the
+        // MainCamera too
+        player.spawn((RenderLayers::layer(0).with(BODY_LAYER), DirectionalLightBundle {
+            transform: Transform::from_xyz(0., 50., -20.).looking_at(Vec3::ZERO, Vec3::Y),
+            directional_light: DirectionalLight {
+                shadows_enabled: true,
                 ..default()
-            });
-        });
+            },
+            ..default()
+        }));
 
         
 
@@ -113,83 +181,210 @@ pub fn player_placement (
     
 }
 
-fn tmp_anim_sword(
-    mut animations: Query<&mut AnimationPlayer, Added<AnimationPlayer>>,
-    assets: Res<GameAssets>,
+pub fn world_builder (
+    mut assets: ResMut<GameAssets>,
+    mut loaded_chunks: ResMut<LoadedChunks>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    server: Res<AssetServer>,
 ){
-    for mut animation in animations.iter_mut() {
-        animation.play(assets.get_animation()).set_speed(0.25).repeat();
+    let world_handle = assets.new_world();
+    loaded_chunks.world_handle = world_handle;
+
+    // chunks stream in from Update, request their assets up front
+    loaded_chunks.material = materials.add(StandardMaterial{
+        base_color_texture: Some(assets.request::<Image>(world_handle, GROUND_TEXTURE, &server)),
+        ..default()
+    });
+
+    assets.load_world(world_handle);
+}
+
+// the ground uvs go past 1 to tile the texture, the default sampler clamps them
+fn repeat_ground_texture(
+    mut done: Local<bool>,
+    loaded_chunks: Res<LoadedChunks>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+){
+    if *done {
+        return;
+    }
+    let Some(texture) = materials.get(&loaded_chunks.material).and_then(|material| material.base_color_texture.clone()) else {
+        return;
+    };
+    let Some(image) = images.get_mut(&texture) else {
+        return;
+    };
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+    *done = true;
+}
+
+// nothing to stand on until the world is loaded and its chunks streamed in
+fn pause_physics(mut rapier: ResMut<RapierConfiguration>) {
+    rapier.physics_pipeline_active = false;
+}
+
+fn resume_physics(
+    mut rapier: ResMut<RapierConfiguration>,
+    loaded_chunks: Res<LoadedChunks>,
+) {
+    if !rapier.physics_pipeline_active && loaded_chunks.center.is_some() {
+        rapier.physics_pipeline_active = true;
     }
 }
 
-pub fn world_builder (
+fn rebuild_terrain(
+    mut events: EventReader<WorldGenChanged>,
+    mut terrain: ResMut<Terrain>,
+    mut player: Query<&mut Transform, With<Player>>,
+    config: Res<WorldGenConfig>,
+    seed: Res<WorldSeed>,
+){
+    if events.read().count() == 0 {
+        return;
+    }
+
+    *terrain = Terrain::new(&config, *seed);
+
+    // the ground may have moved up under the player
+    for mut transform in player.iter_mut() {
+        let ground = terrain.height_at(transform.translation.x, transform.translation.z) + EYE / 2.;
+        transform.translation.y = transform.translation.y.max(ground);
+    }
+}
+
+// drops every chunk after a config or catalogue change, stream_chunks then
+// builds them again with the new parameters
+fn regenerate_chunks(
     mut commands: Commands,
-    player: Query<&Transform, With<Player>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut events: EventReader<WorldGenChanged>,
     mut assets: ResMut<GameAssets>,
     server: Res<AssetServer>,
+    mut loaded_chunks: ResMut<LoadedChunks>,
 ){
-    let world_handle = assets.new_world();
-    
+    if events.read().count() == 0 {
+        return;
+    }
+
+    for (_, chunk) in loaded_chunks.chunks.drain() {
+        commands.entity(chunk).despawn_recursive();
+    }
+    loaded_chunks.center = None;
+
+    // the props of the old world may not come back, the new chunks request theirs again
+    assets.release_world(loaded_chunks.world_handle);
+    loaded_chunks.world_handle = assets.new_world();
+    assets.request::<Image>(loaded_chunks.world_handle, GROUND_TEXTURE, &server);
+}
+
+pub fn stream_chunks (
+    mut commands: Commands,
+    player: Query<&Transform, With<Player>>,
+    mut loaded_chunks: ResMut<LoadedChunks>,
+    mut chunks: Query<(&Chunk, &mut ChunkLod, &mut Handle<Mesh>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    terrain: Res<Terrain>,
+    config: Res<WorldGenConfig>,
+){
+    let Ok(player_transform) = player.get_single() else {
+        return;
+    };
+
+    let center = config.chunk_coords(player_transform.translation);
+    if loaded_chunks.center == Some(center) {
+        return;
+    }
+    loaded_chunks.center = Some(center);
+
+    let size = config.chunk_size;
+    let radius = config.chunk_radius;
+    let unload_radius = radius + config.chunk_hysteresis;
+    loaded_chunks.chunks.retain(|&(x, z), entity| {
+        let keep = (x - center.0).abs() <= unload_radius && (z - center.1).abs() <= unload_radius;
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+        }
+        keep
+    });
+
+    for (chunk, mut lod, mut mesh) in chunks.iter_mut() {
+        let new_lod = config.chunk_lod((chunk.0, chunk.1), center);
+        if lod.0 != new_lod {
+            lod.0 = new_lod;
+            *mesh = meshes.add(chunk_mesh(&terrain, size, chunk.0, chunk.1, new_lod, config.ground_repeat));
+        }
+    }
+
+    for z in (center.1 - radius)..=(center.1 + radius) {
+        for x in (center.0 - radius)..=(center.0 + radius) {
 
-    let player_position = player.single().translation;
-    let chunk_position: (i32, i32) = 
-        ((player_position.x / CHUNK_SIZE as f32).floor() as i32, (player_position.z / CHUNK_SIZE as f32).floor() as i32);
+            if loaded_chunks.chunks.contains_key(&(x, z)) {
+                continue;
+            }
 
-    let new_mesh = gen_flat_mesh(0, 0, CHUNK_SIZE, CHUNK_SIZE);
-    for z in (chunk_position.0 - CHUNK_RADIUS as i32)..(chunk_position.0 + CHUNK_RADIUS as i32) {
-        for x in (chunk_position.1 - CHUNK_RADIUS as i32)..(chunk_position.1 + CHUNK_RADIUS as i32) {
+            let origin = Vec2::new((x * size) as f32, (z * size) as f32);
+            let height_at = |lx: f32, lz: f32| terrain.height_at(origin.x + lx, origin.y + lz);
+            let lod = config.chunk_lod((x, z), center);
 
-            commands.spawn((
+            let chunk = commands.spawn((
             Chunk(x, z),
+            ChunkLod(lod),
             PbrBundle{
-                transform: Transform::from_xyz((x * CHUNK_SIZE) as f32, 0., (z * CHUNK_SIZE) as f32),
-                mesh: meshes.add(new_mesh.clone()),
-                material: materials.add(StandardMaterial{
-                    base_color_texture: Some(assets.request_image(world_handle, "textures/gravier_16px.png".to_string(), &server)),
-                    ..default()
-                }),
+                transform: Transform::from_xyz(origin.x, 0., origin.y),
+                mesh: meshes.add(chunk_mesh(&terrain, size, x, z, lod, config.ground_repeat)),
+                material: loaded_chunks.material.clone(),
                 ..default()
             }))
             .with_children(|ground|{
                 ground.spawn(
-                    Collider::cuboid(CHUNK_SIZE as f32 / 2., 0.25, CHUNK_SIZE as f32 / 2.)
-                ).insert(TransformBundle::from(Transform::from_xyz((CHUNK_SIZE / 2) as f32, -0.5, (CHUNK_SIZE / 2) as f32)));
-            });
+                    gen_terrain_collider(size, size, height_at)
+                ).insert(TransformBundle::from(Transform::from_xyz(size as f32 / 2., 0., size as f32 / 2.)));
+            })
+            .id();
+
+            loaded_chunks.chunks.insert((x, z), chunk);
         }
     }
+}
 
-    let mut rng = rand::thread_rng();
-
-    for _ in 0..60 {
-        
-        let range = 10.0..150.0;
-        let radius = rng.gen_range(range);
-
+// the scatter only depends on the seed and the chunk, so late props land
+// where they would have been
+#[allow(clippy::too_many_arguments)]
+fn decorate_chunks(
+    mut commands: Commands,
+    chunks: Query<(Entity, &Chunk), Without<Decorated>>,
+    loaded_chunks: Res<LoadedChunks>,
+    mut assets: ResMut<GameAssets>,
+    server: Res<AssetServer>,
+    terrain: Res<Terrain>,
+    seed: Res<WorldSeed>,
+    config: Res<WorldGenConfig>,
+    catalogue_handle: Option<Res<CatalogueHandle>>,
+    catalogues: Res<Assets<DecorationCatalogue>>,
+){
+    let Some(catalogue) = catalogue_handle.and_then(|handle| catalogues.get(&handle.0)) else {
+        return;
+    };
+    let size = config.chunk_size;
+    let world_handle = loaded_chunks.world_handle;
 
-        let angle:f32 = rng.gen_range(0.0..2.0 *3.1417);
-        
-        let translation = Vec3::X * radius;
-        let point = translation;
-        let mut stalagmite_transform = Transform::from_xyz(point.x, point.y, point.z);
-        stalagmite_transform.rotate_around(Vec3::ZERO, Quat::from_rotation_y(angle));
-        //spawner_transform .translation += Vec3::Y;
-
-        commands.spawn(SceneBundle{
-            scene: assets.request_scene(world_handle, "models/deco/stalagmite_base.glb#Scene0".to_string(), &server),
-            transform: stalagmite_transform,
-            ..default()
-        }).
-        with_children(|stalagmite|{
+    for (entity, chunk) in chunks.iter() {
+        let origin = Vec2::new((chunk.0 * size) as f32, (chunk.1 * size) as f32);
+        let mut rng = seed.chunk_rng(chunk.0, chunk.1);
 
-            stalagmite.spawn(TransformBundle{
-                local: Transform::from_xyz(0., 6.5, 0.),
-                ..default()
-            }).insert(Collider::cone(6.5, 2.));
+        commands.entity(entity).insert(Decorated).with_children(|ground| {
+            for point in scatter_points(&mut rng, size as f32, &config.decorations) {
+                if let Some(prop) = catalogue.pick(&mut rng) {
+                    let scene = assets.request::<Scene>(world_handle, &prop.scene, &server);
+                    let height = terrain.height_at(origin.x + point.x, origin.y + point.y);
+                    let transform = prop.transform(Vec3::new(point.x, height, point.y), &mut rng);
+                    prop.spawn(ground, scene, transform);
+                }
+            }
         });
-        
     }
-
-    assets.wait_for_world_assets(world_handle, &server);
 }
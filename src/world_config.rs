@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{decoration::{DecorationCatalogue, DecorationSettings}, noise::FractalNoise, ron_loader::RonAssetLoader};
+
+pub const WORLD_CONFIG_PATH: &str = "world.worldgen.ron";
+
+// Sent whenever the generation parameters or the decoration catalogue change,
+// the world throws its chunks away and streams them in again.
+#[derive(Event)]
+pub struct WorldGenChanged;
+
+#[derive(Asset, Resource, TypePath, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct WorldGenConfig {
+    pub chunk_size: i32,
+    pub chunk_radius: i32,
+    // chunks are only unloaded once they are this many chunks past chunk_radius,
+    // so walking back and forth over a border does not respawn them every frame
+    pub chunk_hysteresis: i32,
+    // chunk distance (in chunks) up to which each lod is used, further is the last lod
+    pub lod_distances: Vec<i32>,
+    // None keeps one gravel tile per unit on 4 vertices per cell, Some(repeat)
+    // uses the lighter shared vertex grid with the texture tiling every repeat units
+    pub ground_repeat: Option<f32>,
+    pub terrain: FractalNoise,
+    pub decorations: DecorationSettings,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        WorldGenConfig {
+            chunk_size: 50,
+            chunk_radius: 5,
+            chunk_hysteresis: 2,
+            lod_distances: vec![1, 3],
+            ground_repeat: None,
+            terrain: FractalNoise::default(),
+            decorations: DecorationSettings::default(),
+        }
+    }
+}
+
+impl WorldGenConfig {
+
+    pub fn chunk_coords(&self, position: Vec3) -> (i32, i32) {
+        let size = self.chunk_size as f32;
+        ((position.x / size).floor() as i32, (position.z / size).floor() as i32)
+    }
+
+    pub fn chunk_lod(&self, chunk: (i32, i32), center: (i32, i32)) -> u32 {
+        let distance = (chunk.0 - center.0).abs().max((chunk.1 - center.1).abs());
+        self.lod_distances.iter().filter(|&&max| distance > max).count() as u32
+    }
+
+    // anything that would divide by zero or build a degenerate heightfield
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = |value: f32| value.is_finite() && value > 0.;
+        let non_negative = |value: f32| value.is_finite() && value >= 0.;
+
+        if self.chunk_size < 1 {
+            return Err(format!("chunk_size must be at least 1, got {}", self.chunk_size));
+        }
+        if self.chunk_radius < 0 || self.chunk_hysteresis < 0 {
+            return Err("chunk_radius and chunk_hysteresis cannot be negative".to_string());
+        }
+        if self.lod_distances.first().is_some_and(|&first| first < 0) || self.lod_distances.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(format!("lod_distances must be positive and increasing, got {:?}", self.lod_distances));
+        }
+        // the coarsest cells still have to fit in a chunk
+        if self.lod_distances.len() >= 16 || 1 << self.lod_distances.len() > self.chunk_size {
+            return Err(format!("{} lods are too many for chunks of {} units", self.lod_distances.len() + 1, self.chunk_size));
+        }
+        if self.ground_repeat.is_some_and(|repeat| !positive(repeat)) {
+            return Err("ground_repeat must be positive".to_string());
+        }
+        let terrain = &self.terrain;
+        if !positive(terrain.frequency) || !positive(terrain.lacunarity) {
+            return Err("terrain frequency and lacunarity must be positive".to_string());
+        }
+        if !terrain.amplitude.is_finite() || !terrain.persistence.is_finite() {
+            return Err("terrain amplitude and persistence must be finite".to_string());
+        }
+        let decorations = &self.decorations;
+        if !non_negative(decorations.density) || !non_negative(decorations.min_spacing) {
+            return Err("decoration density and min_spacing cannot be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct WorldConfigHandle(pub Handle<WorldGenConfig>);
+
+pub struct WorldConfigPlugin;
+impl Plugin for WorldConfigPlugin {
+    fn build(&self, app: &mut App){
+
+        app
+        .init_asset::<WorldGenConfig>()
+        .register_asset_loader(RonAssetLoader::<WorldGenConfig>::new(&["worldgen.ron"]).with_validation(WorldGenConfig::validate))
+        .init_resource::<WorldGenConfig>()
+        .add_event::<WorldGenChanged>()
+        .add_systems(Startup, load_world_config)
+        .add_systems(Update, apply_world_config);
+    }
+}
+
+fn load_world_config(
+    mut commands: Commands,
+    server: Res<AssetServer>,
+){
+    commands.insert_resource(WorldConfigHandle(server.load(WORLD_CONFIG_PATH)));
+}
+
+// with the `hot_reload` feature the asset server watches the files, and edits
+// land here as Modified events
+fn apply_world_config(
+    mut config_events: EventReader<AssetEvent<WorldGenConfig>>,
+    mut catalogue_events: EventReader<AssetEvent<DecorationCatalogue>>,
+    handle: Option<Res<WorldConfigHandle>>,
+    configs: Res<Assets<WorldGenConfig>>,
+    mut config: ResMut<WorldGenConfig>,
+    mut writer: EventWriter<WorldGenChanged>,
+){
+    let mut changed = false;
+
+    for event in config_events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } = event {
+            let is_current = handle.as_ref().is_some_and(|handle| handle.0.id() == *id);
+            let Some(new_config) = configs.get(*id).filter(|_| is_current) else {
+                continue;
+            };
+            info!("world generation config loaded");
+            *config = new_config.clone();
+            changed = true;
+        }
+    }
+
+    for event in catalogue_events.read() {
+        if let AssetEvent::Modified { .. } = event {
+            info!("decoration catalogue reloaded");
+            changed = true;
+        }
+    }
+
+    if changed {
+        writer.send(WorldGenChanged);
+    }
+}